@@ -1,51 +1,138 @@
-use std::str::FromStr;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::BufRead,
+    str::FromStr,
+};
 
-use itertools::Itertools;
+use tools::Error;
+
+fn main() -> Result<(), Error> {
+    let mut preamble = 25;
+    let mut stream = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--stream" => stream = true,
+            "--preamble" => preamble = usize::from_str(&value()?)?,
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+    // with fewer than two numbers there is no pair to sum, so nothing is valid
+    if preamble < 2 {
+        return Err("Preamble needs at least 2 numbers".into());
+    }
+    if stream {
+        return run_stream(preamble);
+    }
 
-fn main() {
     let nums: Vec<_> = include_str!(r#"..\input.txt"#)
         .lines()
         .map(|s| usize::from_str(s.trim()).unwrap())
         .collect();
-    let (idx, num) = find_invalid(&nums, 25).unwrap();
-    debug_assert_eq!(num, 1038347917);
-    let window = try_find_window(&nums[0..idx], num).unwrap();
-    debug_assert_eq!(137394018, find_sum(window).unwrap());
-    println!("All done!")
+    let (idx, num) = find_invalid(&nums, preamble).ok_or("No invalid number")?;
+    let window = try_find_window(&nums[0..idx], num).ok_or("No contiguous window")?;
+    let weakness = find_sum(window).unwrap();
+    if preamble == 25 {
+        debug_assert_eq!(num, 1038347917);
+        debug_assert_eq!(137394018, weakness);
+    }
+    println!("First invalid number: {}", num);
+    println!("Encryption weakness: {}", weakness);
+    Ok(())
+}
+
+fn run_stream(preamble: usize) -> Result<(), Error> {
+    let mut validator = Validator::new(preamble);
+    let stdin = std::io::stdin();
+    for (line_no, line) in stdin.lock().lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let num = usize::from_str(line)?;
+        if !validator.push(num) {
+            println!("Invalid: {} (line {})", num, line_no + 1);
+        }
+    }
+    Ok(())
 }
 
-fn read_window(nums: &[usize], start_idx: usize, length: usize) -> &[usize] {
-    &nums[start_idx..start_idx + length]
+/// Sliding window over the last `preamble` numbers, kept as a multiset so that
+/// checking a new number only costs one lookup per window entry.
+#[derive(Debug, Clone)]
+struct Validator {
+    preamble: usize,
+    window: VecDeque<usize>,
+    counts: HashMap<usize, usize>,
 }
 
-fn find_invalid(nums: &[usize], length: usize) -> Option<(usize, usize)> {
-    for idx in length..nums.len() {
-        let window = read_window(nums, idx - length, length);
-        let sums: Vec<_> = window
-            .into_iter()
-            .combinations(2)
-            .map(|com| com.iter().cloned().sum::<usize>())
-            .collect();
-        let num = nums[idx];
-        if sums.contains(&num) {
-            continue;
-        } else {
-            return Some((idx, num));
+impl Validator {
+    fn new(preamble: usize) -> Self {
+        Self {
+            preamble,
+            window: VecDeque::with_capacity(preamble + 1),
+            counts: HashMap::new(),
         }
     }
-    None
+
+    fn is_valid(&self, num: usize) -> bool {
+        if self.window.len() < self.preamble {
+            return true;
+        }
+        self.counts.keys().any(|&a| {
+            num.checked_sub(a).is_some_and(|b| {
+                if a == b {
+                    self.counts[&a] > 1
+                } else {
+                    self.counts.contains_key(&b)
+                }
+            })
+        })
+    }
+
+    /// Checks `num` against the current window, then slides it in.
+    fn push(&mut self, num: usize) -> bool {
+        let valid = self.is_valid(num);
+        self.window.push_back(num);
+        *self.counts.entry(num).or_insert(0) += 1;
+        if self.window.len() > self.preamble {
+            let old = self.window.pop_front().unwrap();
+            if let Some(count) = self.counts.get_mut(&old) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&old);
+                }
+            }
+        }
+        valid
+    }
+}
+
+fn find_invalid(nums: &[usize], length: usize) -> Option<(usize, usize)> {
+    let mut validator = Validator::new(length);
+    nums.iter()
+        .enumerate()
+        .find(|(_, &num)| !validator.push(num))
+        .map(|(idx, &num)| (idx, num))
 }
 
+/// Two-pointer search for a run of at least two numbers summing to `num`.
+/// Relies on all inputs being non-negative.
 fn try_find_window(nums: &[usize], num: usize) -> Option<&[usize]> {
-    for start in 0..nums.len() - 1 {
-        for end in start + 2..nums.len() {
-            let window = &nums[start..end];
-            let sum = window.into_iter().cloned().sum::<usize>();
-            if sum == num {
-                return Some(window);
-            } else if sum > num {
-                break;
-            }
+    let (mut start, mut sum) = (0, 0);
+    for end in 0..nums.len() {
+        sum += nums[end];
+        while sum > num && start < end {
+            sum -= nums[start];
+            start += 1;
+        }
+        if sum == num && end > start {
+            return Some(&nums[start..=end]);
         }
     }
     None
@@ -63,7 +150,7 @@ fn find_sum(nums: &[usize]) -> Option<usize> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    const sample: &str = r#"35
+    const SAMPLE: &str = r#"35
     20
     15
     25
@@ -86,7 +173,7 @@ mod tests {
 
     #[test]
     fn test_find_invalid() {
-        let nums: Vec<_> = sample
+        let nums: Vec<_> = SAMPLE
             .lines()
             .map(|s| usize::from_str(s.trim()).unwrap())
             .collect();
@@ -95,4 +182,16 @@ mod tests {
         let window = try_find_window(&nums[0..idx], 127).unwrap();
         assert_eq!(62, find_sum(window).unwrap())
     }
+
+    #[test]
+    fn test_validator_duplicates() {
+        let mut validator = Validator::new(3);
+        for num in [5, 5, 1].iter() {
+            assert!(validator.push(*num));
+        }
+        assert!(validator.push(10));
+        // the window is now [5, 1, 10], so 10 = 5 + 5 no longer holds
+        assert!(!validator.push(10));
+        assert!(validator.push(11));
+    }
 }
@@ -1,68 +1,208 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::BTreeMap, ops::RangeInclusive, str::FromStr};
+
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+
+type Error = Box<dyn std::error::Error>;
+
+fn main() -> Result<(), Error> {
+    let mut gaps = 1..=3;
+    let mut enumerate = None;
+    let mut sample = None;
+    let mut seed = 0x2020_1210;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--gaps" => gaps = parse_gaps(&value()?).ok_or("Gaps must look like 1-3")?,
+            "--enumerate" => enumerate = Some(usize::from_str(&value()?)?),
+            "--sample" => sample = Some(usize::from_str(&value()?)?),
+            "--seed" => seed = u64::from_str(&value()?)?,
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
 
-fn main() {
     let input = include_str!(r#"..\input.txt"#);
-    let nums = get_nums(&input);
-    let ones = find_diff(&nums, 1);
-    let threes = find_diff(&nums, 3);
-    debug_assert_eq!(2376, ones * threes);
-    debug_assert_eq!(129586085429248, count_arrangements(&nums));
-    println!("All done.");
+    let nums = get_nums(input, &gaps);
+    println!("Joltage differences:");
+    for (diff, count) in diff_histogram(&nums) {
+        println!("  {}: {}", diff, count);
+    }
+    let product = find_diff(&nums, 1) * find_diff(&nums, 3);
+    let ways = count_ways(&nums, &gaps);
+    if gaps == (1..=3) {
+        debug_assert_eq!(2376, product);
+        debug_assert_eq!(BigUint::from(129586085429248u64), ways[0]);
+    }
+    println!("Ones x threes: {}", product);
+    println!("Arrangements: {}", ways[0]);
+    if let Some(limit) = enumerate {
+        for chain in enumerate_chains(&nums, &gaps, limit) {
+            println!("{}", format_chain(&chain));
+        }
+    }
+    if let Some(count) = sample {
+        if ways[0].is_zero() {
+            println!("No valid chain to sample");
+            return Ok(());
+        }
+        let mut rng = XorShift(seed.max(1));
+        for _ in 0..count {
+            let chain = rng
+                .below(&ways[0])
+                .and_then(|rank| nth_chain(&nums, &gaps, &ways, rank))
+                .unwrap();
+            println!("{}", format_chain(&chain));
+        }
+    }
+    Ok(())
+}
+
+fn parse_gaps(s: &str) -> Option<RangeInclusive<u32>> {
+    let (min, max) = match s.find('-') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => ("1", s),
+    };
+    let (min, max) = (u32::from_str(min).ok()?, u32::from_str(max).ok()?);
+    if min <= max && max > 0 {
+        Some(min..=max)
+    } else {
+        None
+    }
+}
+
+fn diff_histogram(nums: &[u32]) -> BTreeMap<u32, usize> {
+    nums.windows(2).fold(BTreeMap::new(), |mut acc, w| {
+        *acc.entry(w[1] - w[0]).or_insert(0) += 1;
+        acc
+    })
 }
 
 fn find_diff(nums: &[u32], diff: u32) -> usize {
-    nums[0..nums.len() - 1]
-        .into_iter()
-        .zip(nums[1..].into_iter())
-        .filter_map(
-            |(left, right)| {
-                if right - left == diff {
-                    Some(())
-                } else {
-                    None
-                }
-            },
-        )
-        .count()
+    diff_histogram(nums).get(&diff).cloned().unwrap_or(0)
+}
+
+/// `ways[i]` is the number of valid chains from adapter `i` to the device.
+fn count_ways(nums: &[u32], gaps: &RangeInclusive<u32>) -> Vec<BigUint> {
+    let mut ways = vec![BigUint::zero(); nums.len()];
+    if let Some(last) = ways.last_mut() {
+        *last = BigUint::one();
+    }
+    for idx in (0..nums.len().saturating_sub(1)).rev() {
+        let count = next_adapters(nums, gaps, idx).fold(BigUint::zero(), |acc, j| acc + &ways[j]);
+        ways[idx] = count;
+    }
+    ways
+}
+
+fn next_adapters<'a>(
+    nums: &'a [u32],
+    gaps: &'a RangeInclusive<u32>,
+    idx: usize,
+) -> impl Iterator<Item = usize> + 'a {
+    let start = nums[idx];
+    (idx + 1..nums.len())
+        .take_while(move |&j| nums[j] - start <= *gaps.end())
+        .filter(move |&j| gaps.contains(&(nums[j] - start)))
 }
 
-fn count_arrangements(nums: &[u32]) -> usize {
-    fn inner(nums: &[u32], result: &mut HashMap<usize, usize>, idx: usize) -> usize {
-        if idx >= nums.len() - 1 {
-            1
-        } else if let Some(&r) = result.get(&idx) {
-            r
-        } else {
-            let count = nums
-                .iter()
-                .skip(idx + 1)
-                .enumerate()
-                .take(3)
-                .filter_map(|(i, v)| {
-                    if v - nums[idx] <= 3 {
-                        Some(inner(nums, result, idx + i + 1))
-                    } else {
-                        None
-                    }
-                })
-                .sum();
-            result.insert(idx, count);
-            count
+fn enumerate_chains(nums: &[u32], gaps: &RangeInclusive<u32>, limit: usize) -> Vec<Vec<u32>> {
+    fn inner(
+        nums: &[u32],
+        gaps: &RangeInclusive<u32>,
+        limit: usize,
+        path: &mut Vec<usize>,
+        result: &mut Vec<Vec<u32>>,
+    ) {
+        let idx = *path.last().unwrap();
+        if idx == nums.len() - 1 {
+            result.push(path.iter().map(|&i| nums[i]).collect());
+            return;
+        }
+        for j in next_adapters(nums, gaps, idx) {
+            if result.len() >= limit {
+                return;
+            }
+            path.push(j);
+            inner(nums, gaps, limit, path, result);
+            path.pop();
         }
     }
 
-    let mut result = HashMap::new();
-    inner(nums, &mut result, 0)
+    let mut result = vec![];
+    if !nums.is_empty() && limit > 0 {
+        inner(nums, gaps, limit, &mut vec![0], &mut result);
+    }
+    result
 }
 
-fn get_nums(input: &str) -> Vec<u32> {
+/// Picks the `rank`-th chain in lexicographic order of adapter indices.
+fn nth_chain(
+    nums: &[u32],
+    gaps: &RangeInclusive<u32>,
+    ways: &[BigUint],
+    mut rank: BigUint,
+) -> Option<Vec<u32>> {
+    if nums.is_empty() || rank >= ways[0] {
+        return None;
+    }
+    let mut idx = 0;
+    let mut chain = vec![nums[0]];
+    while idx < nums.len() - 1 {
+        for j in next_adapters(nums, gaps, idx) {
+            if rank < ways[j] {
+                idx = j;
+                break;
+            }
+            rank -= &ways[j];
+        }
+        chain.push(nums[idx]);
+    }
+    Some(chain)
+}
+
+fn format_chain(chain: &[u32]) -> String {
+    chain
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Close enough to uniform: draws 64 bits more than `bound` needs.
+    /// `None` if `bound` is zero.
+    fn below(&mut self, bound: &BigUint) -> Option<BigUint> {
+        if bound.is_zero() {
+            return None;
+        }
+        let words = bound.bits() as usize / 64 + 2;
+        let digits: Vec<_> = (0..words).map(|_| self.next()).collect();
+        let bytes: Vec<_> = digits.iter().flat_map(|d| d.to_le_bytes()).collect();
+        Some(BigUint::from_bytes_le(&bytes) % bound)
+    }
+}
+
+fn get_nums(input: &str, gaps: &RangeInclusive<u32>) -> Vec<u32> {
     let mut nums: Vec<_> = input
         .split_ascii_whitespace()
         .map(|s| u32::from_str(s.trim()).unwrap())
         .collect();
-    nums.sort();
+    nums.sort_unstable();
     nums.insert(0, 0);
-    nums.push(nums.last().cloned().unwrap() + 3);
+    nums.push(nums.last().cloned().unwrap() + gaps.end());
     nums
 }
 
@@ -70,6 +210,10 @@ fn get_nums(input: &str) -> Vec<u32> {
 mod tests {
     use super::*;
 
+    fn count_arrangements(nums: &[u32], gaps: &RangeInclusive<u32>) -> BigUint {
+        count_ways(nums, gaps).swap_remove(0)
+    }
+
     const SAMPLE1: &str = r#"16
     10
     15
@@ -117,14 +261,14 @@ mod tests {
     #[test]
     fn test_find_diff() {
         {
-            let nums = get_nums(SAMPLE1);
+            let nums = get_nums(SAMPLE1, &(1..=3));
             let ones = find_diff(&nums, 1);
             let threes = find_diff(&nums, 3);
             assert_eq!(7, ones);
             assert_eq!(5, threes);
         }
         {
-            let nums = get_nums(SAMPLE2);
+            let nums = get_nums(SAMPLE2, &(1..=3));
             let ones = find_diff(&nums, 1);
             let threes = find_diff(&nums, 3);
             assert_eq!(22, ones);
@@ -135,12 +279,46 @@ mod tests {
     #[test]
     fn test_find_gaps() {
         {
-            let nums = get_nums(SAMPLE1);
-            assert_eq!(8, count_arrangements(&nums))
+            let nums = get_nums(SAMPLE1, &(1..=3));
+            assert_eq!(BigUint::from(8u32), count_arrangements(&nums, &(1..=3)))
         }
         {
-            let nums = get_nums(SAMPLE2);
-            assert_eq!(19208, count_arrangements(&nums))
+            let nums = get_nums(SAMPLE2, &(1..=3));
+            assert_eq!(BigUint::from(19208u32), count_arrangements(&nums, &(1..=3)))
+        }
+    }
+
+    #[test]
+    fn test_chains() {
+        let gaps = 1..=3;
+        let nums = get_nums(SAMPLE1, &gaps);
+        let chains = enumerate_chains(&nums, &gaps, 100);
+        assert_eq!(8, chains.len());
+        let ways = count_ways(&nums, &gaps);
+        for (rank, chain) in chains.iter().enumerate() {
+            assert_eq!(
+                Some(chain),
+                nth_chain(&nums, &gaps, &ways, BigUint::from(rank)).as_ref()
+            );
         }
+        assert_eq!(None, nth_chain(&nums, &gaps, &ways, BigUint::from(8u32)));
+
+        // a wider tolerance overflows u64 on a long run of consecutive adapters
+        let input: Vec<_> = (1..=200).map(|n| n.to_string()).collect();
+        let nums = get_nums(&input.join("\n"), &(1..=5));
+        assert!(count_arrangements(&nums, &(1..=5)) > BigUint::from(u64::MAX));
+    }
+
+    #[test]
+    fn test_no_chains() {
+        // every step in the sample is 1 or 3, so steps of exactly 2 never work
+        let gaps = 2..=2;
+        let nums = get_nums(SAMPLE1, &gaps);
+        let ways = count_ways(&nums, &gaps);
+        assert!(ways[0].is_zero());
+        assert!(enumerate_chains(&nums, &gaps, 10).is_empty());
+        assert_eq!(None, XorShift(1).below(&ways[0]));
+        assert_eq!(None, nth_chain(&nums, &gaps, &ways, BigUint::zero()));
+        assert!(XorShift(1).below(&BigUint::from(5u32)).unwrap() < BigUint::from(5u32));
     }
 }
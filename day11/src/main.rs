@@ -1,20 +1,58 @@
-use std::collections::HashMap;
-
-use itertools::Itertools;
+use std::{fmt, fs, path::PathBuf, str::FromStr};
 
 use tools::Error;
 
-fn main() {
+fn main() -> Result<(), Error> {
+    let mut rules = vec![];
+    let mut frames = None;
+    let mut max_generations = 10_000;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--rule" => rules.push(SeatingRule::from_str(&value()?)?),
+            "--frames" => frames = Some(FrameOutput::from_str(&value()?)?),
+            "--max-generations" => max_generations = usize::from_str(&value()?)?,
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+    if rules.is_empty() {
+        rules = vec![SeatingRule::ADJACENT, SeatingRule::VISIBLE];
+    }
+
     let input = include_str!(r#"..\input.txt"#);
-    let seats = get_seat_map(input).unwrap();
-    debug_assert_eq!(2406, reach_stable(seats.clone()));
-    let (col_count, row_count) = get_dimensions(input).unwrap();
-    debug_assert_eq!(
-        2149,
-        reach_stable_visible(seats, col_count - 1, row_count - 1)
-    );
-
-    println!("All done");
+    let seats = SeatMap::new(input)?;
+    for rule in rules {
+        let (stable, generations) = match &frames {
+            Some(output) => {
+                let mut result = Ok(());
+                let stable =
+                    run_until_stable(&seats, &rule, max_generations, |generation, frame| {
+                        if result.is_ok() {
+                            result = output.write(&rule, generation, frame);
+                        }
+                    });
+                result?;
+                stable?
+            }
+            None => run_until_stable(&seats, &rule, max_generations, |_, _| {})?,
+        };
+        if rule == SeatingRule::ADJACENT {
+            debug_assert_eq!(2406, stable.occupied());
+        } else if rule == SeatingRule::VISIBLE {
+            debug_assert_eq!(2149, stable.occupied());
+        }
+        println!(
+            "{}: {} occupied, stable after {} generations",
+            rule,
+            stable.occupied(),
+            generations
+        );
+    }
+    Ok(())
 }
 
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
@@ -34,272 +72,350 @@ impl Status {
         }
     }
 
-    fn is_empty(&self) -> bool {
+    fn to_char(self) -> char {
         match self {
-            Self::Floor | Self::Empty => true,
-            _ => false,
+            Self::Floor => '.',
+            Self::Empty => 'L',
+            Self::Occupied => '#',
         }
     }
-}
 
-fn reach_stable_visible(mut seats: HashMap<(u8, u8), Status>, max_col: u8, max_row: u8) -> usize {
-    let new_seats = get_changed_seats_visible(&seats, max_col, max_row);
-    if new_seats.is_empty() {
-        seats
-            .iter()
-            .filter(|(_, status)| **status == Status::Occupied)
-            .count()
-    } else {
-        seats.extend(new_seats.into_iter());
-        reach_stable_visible(seats, max_col, max_row)
+    fn to_rgb(self) -> [u8; 3] {
+        match self {
+            Self::Floor => [0x20, 0x20, 0x20],
+            Self::Empty => [0x30, 0xa0, 0x30],
+            Self::Occupied => [0xd0, 0x30, 0x30],
+        }
     }
 }
 
-fn get_changed_seats_visible(
-    seats: &HashMap<(u8, u8), Status>,
-    max_col: u8,
-    max_row: u8,
-) -> HashMap<(u8, u8), Status> {
-    seats
-        .iter()
-        .filter_map(|((col, row), _)| {
-            match change_seat_status_visible(*col, *row, max_col, max_row, seats) {
-                Some(status) => Some(((*col, *row), status)),
-                None => None,
-            }
-        })
-        .collect()
+/// Row-major grid of seats.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SeatMap {
+    width: usize,
+    height: usize,
+    cells: Vec<Status>,
 }
 
-fn change_seat_status_visible(
-    col: u8,
-    row: u8,
-    max_col: u8,
-    max_row: u8,
-    seats: &HashMap<(u8, u8), Status>,
-) -> Option<Status> {
-    let occupied = count_visible_occupied(col, row, max_col, max_row, seats);
-    match seats.get(&(col, row)).cloned().unwrap_or(Status::Floor) {
-        Status::Floor => None,
-        Status::Empty => match occupied {
-            0 => Some(Status::Occupied),
-            _ => None,
-        },
-        Status::Occupied => {
-            if occupied >= 5 {
-                Some(Status::Empty)
-            } else {
-                None
+const DIRECTIONS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+impl SeatMap {
+    fn new(input: &str) -> Result<Self, Error> {
+        let mut width = 0;
+        let mut height = 0;
+        let mut cells = vec![];
+        for line in input.split_ascii_whitespace() {
+            let row = line
+                .chars()
+                .map(Status::new)
+                .collect::<Result<Vec<_>, Error>>()?;
+            if height == 0 {
+                width = row.len();
+            } else if row.len() != width {
+                return Err(
+                    format!("Row {} has {} seats, expected {}", height, row.len(), width).into(),
+                );
             }
+            cells.extend(row);
+            height += 1;
         }
-    }
-}
-
-fn count_visible_occupied(
-    col: u8,
-    row: u8,
-    max_col: u8,
-    max_row: u8,
-    seats: &HashMap<(u8, u8), Status>,
-) -> u8 {
-    fn is_seat(col: u8, row: u8, seats: &HashMap<(u8, u8), Status>) -> bool {
-        if let Some(status) = seats.get(&(col, row)) {
-            *status == Status::Occupied || *status == Status::Empty
-        } else {
-            false
+        if cells.is_empty() {
+            return Err("Empty input".into());
         }
+        Ok(Self {
+            width,
+            height,
+            cells,
+        })
     }
-    fn is_occupied(col: u8, row: u8, seats: &HashMap<(u8, u8), Status>) -> bool {
-        if let Some(status) = seats.get(&(col, row)) {
-            *status == Status::Occupied
+
+    fn step_from(&self, idx: usize, (dx, dy): (isize, isize)) -> Option<usize> {
+        let col = (idx % self.width).checked_add_signed(dx)?;
+        let row = (idx / self.width).checked_add_signed(dy)?;
+        if col < self.width && row < self.height {
+            Some(row * self.width + col)
         } else {
-            false
+            None
         }
     }
 
-    let mut occupied = 0;
-
-    // left
-    if (0..col)
-        .rev()
-        .find(|i| is_seat(*i, row, seats))
-        .filter(|i| is_occupied(*i, row, seats))
-        .is_some()
-    {
-        occupied += 1;
+    /// For every seat, the indices of the seats it takes into account. Floor
+    /// cells get an empty list. `max_range` of `None` looks as far as the grid
+    /// goes, skipping over floor.
+    fn neighbors(&self, max_range: Option<usize>) -> Vec<Vec<usize>> {
+        (0..self.cells.len())
+            .map(|idx| {
+                if self.cells[idx] == Status::Floor {
+                    return vec![];
+                }
+                DIRECTIONS
+                    .iter()
+                    .filter_map(|&dir| {
+                        let mut cur = idx;
+                        let mut dist = 0;
+                        loop {
+                            cur = self.step_from(cur, dir)?;
+                            dist += 1;
+                            if self.cells[cur] != Status::Floor {
+                                return Some(cur);
+                            }
+                            if max_range.is_some_and(|r| dist >= r) {
+                                return None;
+                            }
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
     }
 
-    //right
-    if (col + 1..=max_col)
-        .find(|i| is_seat(*i, row, seats))
-        .filter(|i| is_occupied(*i, row, seats))
-        .is_some()
-    {
-        occupied += 1;
+    fn occupied(&self) -> usize {
+        self.cells
+            .iter()
+            .filter(|&&status| status == Status::Occupied)
+            .count()
     }
+}
 
-    // up
-    if (0..row)
-        .rev()
-        .find(|i| is_seat(col, *i, seats))
-        .filter(|i| is_occupied(col, *i, seats))
-        .is_some()
-    {
-        occupied += 1;
+impl fmt::Display for SeatMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.cells.chunks(self.width) {
+            writeln!(f, "{}", row.iter().map(|s| s.to_char()).collect::<String>())?;
+        }
+        Ok(())
     }
+}
 
-    // down
-    if (row + 1..=max_row)
-        .find(|i| is_seat(col, *i, seats))
-        .filter(|i| is_occupied(col, *i, seats))
-        .is_some()
-    {
-        occupied += 1;
-    }
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Neighborhood {
+    /// The eight cells around a seat.
+    Adjacent,
+    /// The first seat in each of the eight directions, looking past floor for
+    /// at most `max_range` cells when given.
+    LineOfSight { max_range: Option<usize> },
+}
 
-    // top-left
-    if (0..col)
-        .rev()
-        .zip((0..row).rev())
-        .find(|(x, y)| is_seat(*x, *y, seats))
-        .filter(|(x, y)| is_occupied(*x, *y, seats))
-        .is_some()
-    {
-        occupied += 1;
-    }
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct SeatingRule {
+    neighborhood: Neighborhood,
+    /// Number of occupied neighbors that makes an occupied seat empty.
+    tolerance: usize,
+}
 
-    // top-right
-    if (col + 1..=max_col)
-        .zip((0..row).rev())
-        .find(|(x, y)| is_seat(*x, *y, seats))
-        .filter(|(x, y)| is_occupied(*x, *y, seats))
-        .is_some()
-    {
-        occupied += 1;
-    }
+impl SeatingRule {
+    const ADJACENT: Self = Self {
+        neighborhood: Neighborhood::Adjacent,
+        tolerance: 4,
+    };
 
-    // bottom-left
-    if (0..col)
-        .rev()
-        .zip(row + 1..=max_row)
-        .find(|(x, y)| is_seat(*x, *y, seats))
-        .filter(|(x, y)| is_occupied(*x, *y, seats))
-        .is_some()
-    {
-        occupied += 1;
-    }
+    const VISIBLE: Self = Self {
+        neighborhood: Neighborhood::LineOfSight { max_range: None },
+        tolerance: 5,
+    };
 
-    // bottom-right
-    if (col + 1..=max_col)
-        .zip(row + 1..=max_row)
-        .find(|(x, y)| is_seat(*x, *y, seats))
-        .filter(|(x, y)| is_occupied(*x, *y, seats))
-        .is_some()
-    {
-        occupied += 1;
+    fn neighbors(&self, seats: &SeatMap) -> Vec<Vec<usize>> {
+        match self.neighborhood {
+            Neighborhood::Adjacent => seats.neighbors(Some(1)),
+            Neighborhood::LineOfSight { max_range } => seats.neighbors(max_range),
+        }
     }
-
-    occupied
 }
 
-fn get_dimensions(input: &str) -> Result<(u8, u8), Error> {
-    let row_count = input.split_ascii_whitespace().count();
-    let col_count = input
-        .split_ascii_whitespace()
-        .next()
-        .ok_or_else(|| -> Error { "Empty input".into() })?
-        .len();
-    Ok((col_count as u8, row_count as u8))
+/// Accepts `adjacent`, `sight` or `sight:<range>` with a range of at least 1,
+/// optionally followed by `/<tolerance>`, e.g. `sight:3/4`.
+impl FromStr for SeatingRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(2, '/');
+        let kind = parts.next().unwrap_or_default();
+        let tolerance = parts.next().map(usize::from_str).transpose()?;
+        let mut rule = match kind {
+            "adjacent" => Self::ADJACENT,
+            "sight" | "visible" => Self::VISIBLE,
+            _ => match kind.strip_prefix("sight:") {
+                Some(range) => match usize::from_str(range)? {
+                    0 => return Err(format!("Sight range must be at least 1 in {}", s).into()),
+                    range => Self {
+                        neighborhood: Neighborhood::LineOfSight {
+                            max_range: Some(range),
+                        },
+                        ..Self::VISIBLE
+                    },
+                },
+                None => return Err(format!("Invalid rule {}", s).into()),
+            },
+        };
+        if let Some(tolerance) = tolerance {
+            rule.tolerance = tolerance;
+        }
+        Ok(rule)
+    }
 }
 
-fn reach_stable(mut seats: HashMap<(u8, u8), Status>) -> usize {
-    let new_seats = get_changed_seats(&seats);
-    if new_seats.is_empty() {
-        seats
-            .iter()
-            .filter(|(_, status)| **status == Status::Occupied)
-            .count()
-    } else {
-        seats.extend(new_seats.into_iter());
-        reach_stable(seats)
+impl fmt::Display for SeatingRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.neighborhood {
+            Neighborhood::Adjacent => write!(f, "adjacent")?,
+            Neighborhood::LineOfSight { max_range: None } => write!(f, "sight")?,
+            Neighborhood::LineOfSight {
+                max_range: Some(range),
+            } => write!(f, "sight:{}", range)?,
+        }
+        write!(f, "/{}", self.tolerance)
     }
 }
 
-fn get_changed_seats(seats: &HashMap<(u8, u8), Status>) -> HashMap<(u8, u8), Status> {
-    seats
-        .iter()
-        .filter_map(
-            |((col, row), _)| match change_seat_status(*col, *row, seats) {
-                Some(status) => Some(((*col, *row), status)),
-                None => None,
-            },
-        )
-        .collect()
+#[derive(Debug, Clone)]
+enum FrameOutput {
+    Text,
+    /// One binary PPM per generation in the given directory, each seat drawn
+    /// as a `scale`-sized square.
+    Ppm {
+        dir: PathBuf,
+        scale: usize,
+    },
 }
 
-fn change_seat_status(col: u8, row: u8, seats: &HashMap<(u8, u8), Status>) -> Option<Status> {
-    let surrouding = get_surrouding(col, row);
-    match seats.get(&(col, row)).cloned().unwrap_or(Status::Floor) {
-        Status::Floor => None,
-        Status::Empty => {
-            let empty_count = surrouding
-                .iter()
-                .filter(|(col, row)| match seats.get(&(*col, *row)) {
-                    Some(status) => status.is_empty(),
-                    None => true,
-                })
-                .count();
-            if empty_count == surrouding.len() {
-                Some(Status::Occupied)
-            } else {
-                None
+/// Accepts `text`, `ppm:<dir>` or `ppm:<dir>:<scale>`.
+impl FromStr for FrameOutput {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "text" {
+            return Ok(Self::Text);
+        }
+        let spec = s
+            .strip_prefix("ppm:")
+            .ok_or_else(|| format!("Invalid frame output {}", s))?;
+        let (dir, scale) = match spec.rfind(':') {
+            Some(i) if spec[i + 1..].parse::<usize>().is_ok() => {
+                (&spec[..i], usize::from_str(&spec[i + 1..])?)
+            }
+            _ => (spec, 4),
+        };
+        Ok(Self::Ppm {
+            dir: PathBuf::from(dir),
+            scale: scale.max(1),
+        })
+    }
+}
+
+impl FrameOutput {
+    fn write(&self, rule: &SeatingRule, generation: usize, seats: &SeatMap) -> Result<(), Error> {
+        match self {
+            Self::Text => {
+                println!("{} generation {}:", rule, generation);
+                println!("{}", seats);
+            }
+            Self::Ppm { dir, scale } => {
+                fs::create_dir_all(dir)?;
+                let name = format!("{}_{:04}.ppm", rule, generation)
+                    .replace(|c: char| !c.is_ascii_alphanumeric() && c != '.', "_");
+                fs::write(dir.join(name), to_ppm(seats, *scale))?;
             }
         }
-        Status::Occupied => {
-            let occupied_count = surrouding
-                .iter()
-                .filter(|(col, row)| match seats.get(&(*col, *row)) {
-                    Some(status) => *status == Status::Occupied,
-                    None => false,
-                })
-                .count();
-            if occupied_count >= 4 {
-                Some(Status::Empty)
-            } else {
-                None
+        Ok(())
+    }
+}
+
+fn to_ppm(seats: &SeatMap, scale: usize) -> Vec<u8> {
+    let (width, height) = (seats.width * scale, seats.height * scale);
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.reserve(width * height * 3);
+    for row in seats.cells.chunks(seats.width) {
+        for _ in 0..scale {
+            for status in row {
+                for _ in 0..scale {
+                    data.extend_from_slice(&status.to_rgb());
+                }
             }
         }
     }
+    data
 }
 
-fn get_surrouding(col: u8, row: u8) -> Vec<(u8, u8)> {
-    (col.saturating_sub(1)..=col.saturating_add(1))
-        .into_iter()
-        .cartesian_product((row.saturating_sub(1)..=row.saturating_add(1)).into_iter())
-        .filter(|(x, y)| *x != col || *y != row)
-        .collect()
+/// Runs `rule` until nothing changes, handing every generation (starting with
+/// the initial layout as generation 0) to `on_frame`. Returns the stable
+/// layout and the number of generations that changed something, or an error
+/// if the layout still changes after `max_generations`.
+fn run_until_stable<F>(
+    seats: &SeatMap,
+    rule: &SeatingRule,
+    max_generations: usize,
+    mut on_frame: F,
+) -> Result<(SeatMap, usize), Error>
+where
+    F: FnMut(usize, &SeatMap),
+{
+    let neighbors = rule.neighbors(seats);
+    let mut current = seats.clone();
+    let mut next = seats.cells.clone();
+    let mut generation = 0;
+    on_frame(generation, &current);
+    while step(&current.cells, &mut next, &neighbors, rule.tolerance) {
+        if generation == max_generations {
+            return Err(format!("{} not stable after {} generations", rule, generation).into());
+        }
+        std::mem::swap(&mut current.cells, &mut next);
+        generation += 1;
+        on_frame(generation, &current);
+    }
+    Ok((current, generation))
 }
 
-fn get_seat_map(input: &str) -> Result<HashMap<(u8, u8), Status>, Error> {
-    let seats = input
-        .split_ascii_whitespace()
-        .enumerate()
-        .map(|(row, line)| {
-            line.trim()
-                .char_indices()
-                .map(move |(col, ch)| -> Result<((u8, u8), Status), Error> {
-                    Ok(((col as u8, row as u8), Status::new(ch)?))
-                })
-        })
-        .flatten()
-        .collect::<Result<HashMap<_, _>, Error>>()?;
-    Ok(seats)
+/// Writes the next generation of `current` into `next`, returns whether
+/// anything changed.
+fn step(
+    current: &[Status],
+    next: &mut [Status],
+    neighbors: &[Vec<usize>],
+    tolerance: usize,
+) -> bool {
+    let mut changed = false;
+    for (idx, (&status, seen)) in current.iter().zip(neighbors).enumerate() {
+        let occupied = || {
+            seen.iter()
+                .filter(|&&n| current[n] == Status::Occupied)
+                .count()
+        };
+        next[idx] = match status {
+            Status::Empty if occupied() == 0 => Status::Occupied,
+            Status::Occupied if occupied() >= tolerance => Status::Empty,
+            _ => status,
+        };
+        changed |= next[idx] != status;
+    }
+    changed
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn reach_stable(seats: &SeatMap) -> usize {
+        run_until_stable(seats, &SeatingRule::ADJACENT, usize::MAX, |_, _| {})
+            .unwrap()
+            .0
+            .occupied()
+    }
+
+    fn reach_stable_visible(seats: &SeatMap) -> usize {
+        run_until_stable(seats, &SeatingRule::VISIBLE, usize::MAX, |_, _| {})
+            .unwrap()
+            .0
+            .occupied()
+    }
+
     const SAMPLE: &str = r#"L.LL.LL.LL
     LLLLLLL.LL
     L.L.L..L..
@@ -313,19 +429,81 @@ mod tests {
 
     #[test]
     fn test_reach_stable() {
-        let seats = get_seat_map(SAMPLE).unwrap();
-        assert_eq!(37, reach_stable(seats));
+        let seats = SeatMap::new(SAMPLE).unwrap();
+        assert_eq!(37, reach_stable(&seats));
     }
 
     #[test]
     fn test_reach_stable_visible() {
-        let (col_count, row_count) = get_dimensions(SAMPLE).unwrap();
-        assert_eq!(10, col_count);
-        assert_eq!(10, row_count);
-        let seats = get_seat_map(SAMPLE).unwrap();
+        let seats = SeatMap::new(SAMPLE).unwrap();
+        assert_eq!(10, seats.width);
+        assert_eq!(10, seats.height);
+        assert_eq!(26, reach_stable_visible(&seats))
+    }
+
+    #[test]
+    fn test_large_layout() {
+        // the sample tucked into the far corner of a floor-filled hall
+        let offset = 300;
+        let sample: Vec<_> = SAMPLE.split_ascii_whitespace().collect();
+        let width = offset + sample[0].len();
+        let mut rows = vec![".".repeat(width); offset];
+        rows.extend(
+            sample
+                .iter()
+                .map(|line| format!("{}{}", ".".repeat(offset), line)),
+        );
+        let seats = SeatMap::new(&rows.join("\n")).unwrap();
+        assert_eq!(310, seats.width);
+        assert_eq!(310, seats.height);
+        assert_eq!(37, reach_stable(&seats));
+        assert_eq!(26, reach_stable_visible(&seats));
+
+        // a single long row fills up and stays full
+        let seats = SeatMap::new(&"L".repeat(1000)).unwrap();
+        assert_eq!(1000, reach_stable(&seats));
+        assert_eq!(1000, reach_stable_visible(&seats));
+    }
+
+    #[test]
+    fn test_seating_rules() {
+        let seats = SeatMap::new(SAMPLE).unwrap();
+        let mut frames = vec![];
+        let (stable, generations) =
+            run_until_stable(&seats, &SeatingRule::ADJACENT, 100, |_, f| {
+                frames.push(f.to_string())
+            })
+            .unwrap();
+        assert_eq!(37, stable.occupied());
+        assert_eq!(5, generations);
+        assert_eq!(6, frames.len());
+        assert_eq!(
+            "#.##.##.##\n#######.##\n",
+            &frames[1][..22],
+            "every seat fills up in the first round"
+        );
+
+        let rule = SeatingRule::from_str("sight").unwrap();
+        assert_eq!(SeatingRule::VISIBLE, rule);
         assert_eq!(
             26,
-            reach_stable_visible(seats, col_count - 1, row_count - 1)
-        )
+            run_until_stable(&seats, &rule, 100, |_, _| {})
+                .unwrap()
+                .0
+                .occupied()
+        );
+        // line of sight limited to one cell is plain adjacency
+        let rule = SeatingRule::from_str("sight:1/4").unwrap();
+        assert_eq!("sight:1/4", rule.to_string());
+        assert_eq!(
+            37,
+            run_until_stable(&seats, &rule, 100, |_, _| {})
+                .unwrap()
+                .0
+                .occupied()
+        );
+        assert!(SeatingRule::from_str("diagonal").is_err());
+        assert!(SeatingRule::from_str("sight:0").is_err());
+        assert!(run_until_stable(&seats, &SeatingRule::ADJACENT, 4, |_, _| {}).is_err());
     }
 }
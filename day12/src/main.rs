@@ -1,24 +1,74 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 type Error = Box<dyn std::error::Error>;
 
-fn main() {
+fn main() -> Result<(), Error> {
+    let mut use_waypoint = false;
+    let mut use_float = false;
+    let mut svg = None;
+    let mut csv = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--waypoint" => use_waypoint = true,
+            "--float" => use_float = true,
+            "--svg" => svg = Some(args.next().ok_or("Missing SVG path")?),
+            "--csv" => csv = Some(args.next().ok_or("Missing CSV path")?),
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+
     let input = include_str!(r#"..\input.txt"#);
-    let insts = Instruction::read(input).unwrap();
+    let insts = Instruction::read(input)?;
     {
         let mut ship = Ship::new();
-        ship.apply_insts(&insts, Ship::apply_inst).unwrap();
-        debug_assert_eq!(2879, ship.manhattan_dist())
+        ship.apply_insts(&insts, Ship::apply_inst)?;
+        debug_assert_eq!(2879, ship.manhattan_dist());
+        println!("Manhattan distance: {}", ship.manhattan_dist());
     }
     {
         let mut ship = Ship::new();
-        ship.apply_insts(&insts, Ship::apply_inst_wp).unwrap();
-        debug_assert_eq!(178986, ship.manhattan_dist())
+        ship.apply_insts(&insts, Ship::apply_inst_wp)?;
+        debug_assert_eq!(178986, ship.manhattan_dist());
+        println!("Manhattan distance by waypoint: {}", ship.manhattan_dist());
+    }
+    if use_float {
+        let mut ship = FloatShip::new();
+        trace(&mut ship, &insts, FloatShip::apply_inst, false)?;
+        println!("Float distance: {}", ship.x.abs() + ship.y.abs());
+        let mut ship = FloatShip::new();
+        trace(&mut ship, &insts, FloatShip::apply_inst_wp, true)?;
+        println!(
+            "Float distance by waypoint: {}",
+            ship.x.abs() + ship.y.abs()
+        );
     }
-    println!("All done")
+
+    if svg.is_none() && csv.is_none() {
+        return Ok(());
+    }
+    // the flags only pick which route gets exported
+    let track = match (use_float, use_waypoint) {
+        (false, false) => trace(&mut Ship::new(), &insts, Ship::apply_inst, false)?,
+        (false, true) => trace(&mut Ship::new(), &insts, Ship::apply_inst_wp, true)?,
+        (true, false) => trace(&mut FloatShip::new(), &insts, FloatShip::apply_inst, false)?,
+        (true, true) => trace(
+            &mut FloatShip::new(),
+            &insts,
+            FloatShip::apply_inst_wp,
+            true,
+        )?,
+    };
+    if let Some(path) = svg {
+        std::fs::write(path, to_svg(&track))?;
+    }
+    if let Some(path) = csv {
+        std::fs::write(path, to_csv(&track))?;
+    }
+    Ok(())
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Direction {
     North,
     South,
@@ -26,33 +76,31 @@ enum Direction {
     East,
 }
 
+/// Counter-clockwise quarter turns in `0..4` for any multiple of 90 degrees.
+fn quarter_turns(degree: i32) -> Result<i32, Error> {
+    if degree % 90 == 0 {
+        Ok((degree / 90).rem_euclid(4))
+    } else {
+        Err(format!("Invalid rotation degree {}", degree).into())
+    }
+}
+
 impl Direction {
     fn rotate(&self, degree: i32) -> Result<Self, Error> {
-        match degree {
-            90 | -270 => match self {
-                Self::East => Ok(Self::North),
-                Self::North => Ok(Self::West),
-                Self::West => Ok(Self::South),
-                Self::South => Ok(Self::East),
-            },
-            -90 | 270 => match self {
-                Self::East => Ok(Self::South),
-                Self::South => Ok(Self::West),
-                Self::West => Ok(Self::North),
-                Self::North => Ok(Self::East),
-            },
-            180 | -180 => match self {
-                Self::East => Ok(Self::West),
-                Self::West => Ok(Self::East),
-                Self::North => Ok(Self::South),
-                Self::South => Ok(Self::North),
-            },
-            _ => Err(format!("Invalid rotation degree {}", degree).into()),
+        let mut dir = *self;
+        for _ in 0..quarter_turns(degree)? {
+            dir = match dir {
+                Self::East => Self::North,
+                Self::North => Self::West,
+                Self::West => Self::South,
+                Self::South => Self::East,
+            };
         }
+        Ok(dir)
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Instruction {
     N(i32),
     S(i32),
@@ -82,11 +130,25 @@ impl FromStr for Instruction {
     }
 }
 
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::N(num) => write!(f, "N{}", num),
+            Self::S(num) => write!(f, "S{}", num),
+            Self::W(num) => write!(f, "W{}", num),
+            Self::E(num) => write!(f, "E{}", num),
+            Self::L(num) => write!(f, "L{}", num),
+            Self::R(num) => write!(f, "R{}", num),
+            Self::F(num) => write!(f, "F{}", num),
+        }
+    }
+}
+
 impl Instruction {
     fn read(input: &str) -> Result<Vec<Instruction>, Error> {
         let insts = input
             .split_ascii_whitespace()
-            .map(|line| Instruction::from_str(line))
+            .map(Instruction::from_str)
             .collect::<Result<Vec<_>, _>>()?;
         Ok(insts)
     }
@@ -104,14 +166,11 @@ impl Waypoint {
     }
 
     fn rotate(&mut self, degree: i32) -> Result<(), Error> {
-        let (x, y) = match degree {
-            90 | -270 => (-self.y, self.x),
-            -90 | 270 => (self.y, -self.x),
-            180 | -180 => (-self.x, -self.y),
-            _ => return Err(format!("Invalid rotation degree {}", degree).into()),
-        };
-        self.x = x;
-        self.y = y;
+        for _ in 0..quarter_turns(degree)? {
+            let (x, y) = (-self.y, self.x);
+            self.x = x;
+            self.y = y;
+        }
         Ok(())
     }
 
@@ -174,10 +233,7 @@ impl Ship {
         insts: &[Instruction],
         func: fn(&mut Ship, &Instruction) -> Result<(), Error>,
     ) -> Result<(), Error> {
-        for inst in insts {
-            func(self, inst)?;
-        }
-        Ok(())
+        trace(self, insts, func, false).map(|_| ())
     }
 
     fn apply_inst_wp(&mut self, inst: &Instruction) -> Result<(), Error> {
@@ -192,6 +248,202 @@ impl Ship {
     }
 }
 
+/// Same navigation as `Ship`, but with a float heading so that turns of any
+/// angle work.
+#[derive(Debug, Copy, Clone)]
+struct FloatShip {
+    x: f64,
+    y: f64,
+    /// Degrees counter-clockwise from east.
+    heading: f64,
+    wp: (f64, f64),
+}
+
+impl FloatShip {
+    fn new() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            heading: 0.0,
+            wp: (10.0, 1.0),
+        }
+    }
+
+    fn rotate(point: (f64, f64), degree: f64) -> (f64, f64) {
+        let (sin, cos) = degree.to_radians().sin_cos();
+        (point.0 * cos - point.1 * sin, point.0 * sin + point.1 * cos)
+    }
+
+    fn apply_inst(&mut self, inst: &Instruction) -> Result<(), Error> {
+        match *inst {
+            Instruction::N(num) => self.y += f64::from(num),
+            Instruction::S(num) => self.y -= f64::from(num),
+            Instruction::W(num) => self.x -= f64::from(num),
+            Instruction::E(num) => self.x += f64::from(num),
+            Instruction::L(num) => self.heading = (self.heading + f64::from(num)).rem_euclid(360.0),
+            Instruction::R(num) => self.heading = (self.heading - f64::from(num)).rem_euclid(360.0),
+            Instruction::F(num) => {
+                let (dx, dy) = Self::rotate((1.0, 0.0), self.heading);
+                self.x += f64::from(num) * dx;
+                self.y += f64::from(num) * dy;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_inst_wp(&mut self, inst: &Instruction) -> Result<(), Error> {
+        match *inst {
+            Instruction::N(num) => self.wp.1 += f64::from(num),
+            Instruction::S(num) => self.wp.1 -= f64::from(num),
+            Instruction::W(num) => self.wp.0 -= f64::from(num),
+            Instruction::E(num) => self.wp.0 += f64::from(num),
+            Instruction::L(num) => self.wp = Self::rotate(self.wp, f64::from(num)),
+            Instruction::R(num) => self.wp = Self::rotate(self.wp, -f64::from(num)),
+            Instruction::F(num) => {
+                self.x += f64::from(num) * self.wp.0;
+                self.y += f64::from(num) * self.wp.1;
+            }
+        }
+        Ok(())
+    }
+}
+
+trait Vessel {
+    fn position(&self) -> (f64, f64);
+    /// Waypoint relative to the ship.
+    fn waypoint(&self) -> (f64, f64);
+}
+
+impl Vessel for Ship {
+    fn position(&self) -> (f64, f64) {
+        (f64::from(self.x), f64::from(self.y))
+    }
+
+    fn waypoint(&self) -> (f64, f64) {
+        (f64::from(self.wp.x), f64::from(self.wp.y))
+    }
+}
+
+impl Vessel for FloatShip {
+    fn position(&self) -> (f64, f64) {
+        (self.x, self.y)
+    }
+
+    fn waypoint(&self) -> (f64, f64) {
+        self.wp
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct TrackPoint {
+    /// `None` for the starting point.
+    inst: Option<Instruction>,
+    ship: (f64, f64),
+    /// `None` unless the ship navigates by waypoint.
+    waypoint: Option<(f64, f64)>,
+}
+
+/// Applies every instruction in turn and records where the ship, and with
+/// `with_waypoint` its waypoint, end up after each one, starting with the
+/// initial state.
+fn trace<V: Vessel>(
+    vessel: &mut V,
+    insts: &[Instruction],
+    func: fn(&mut V, &Instruction) -> Result<(), Error>,
+    with_waypoint: bool,
+) -> Result<Vec<TrackPoint>, Error> {
+    let point = |vessel: &V, inst| TrackPoint {
+        inst,
+        ship: vessel.position(),
+        waypoint: if with_waypoint {
+            Some(vessel.waypoint())
+        } else {
+            None
+        },
+    };
+    let mut track = vec![point(vessel, None)];
+    for inst in insts {
+        func(vessel, inst)?;
+        track.push(point(vessel, Some(*inst)));
+    }
+    Ok(track)
+}
+
+/// One row per step; the waypoint columns are only written when the track
+/// has a waypoint.
+fn to_csv(track: &[TrackPoint]) -> String {
+    let with_waypoint = track.iter().any(|p| p.waypoint.is_some());
+    let mut csv = String::from("step,instruction,ship_x,ship_y");
+    if with_waypoint {
+        csv += ",waypoint_x,waypoint_y";
+    }
+    csv.push('\n');
+    for (step, point) in track.iter().enumerate() {
+        csv += &format!(
+            "{},{},{},{}",
+            step,
+            point.inst.map(|i| i.to_string()).unwrap_or_default(),
+            point.ship.0,
+            point.ship.1
+        );
+        if let Some((x, y)) = point.waypoint {
+            csv += &format!(",{},{}", x, y);
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Ship route as a solid polyline and, when navigating by waypoint, the
+/// absolute waypoint positions as a dashed one. North is up.
+fn to_svg(track: &[TrackPoint]) -> String {
+    let ship: Vec<_> = track.iter().map(|p| (p.ship.0, -p.ship.1)).collect();
+    let waypoint: Vec<_> = track
+        .iter()
+        .filter_map(|p| p.waypoint.map(|(x, y)| (p.ship.0 + x, -(p.ship.1 + y))))
+        .collect();
+    let (min_x, min_y, max_x, max_y) = ship.iter().chain(&waypoint).fold(
+        (0.0f64, 0.0f64, 0.0f64, 0.0f64),
+        |(min_x, min_y, max_x, max_y), &(x, y)| {
+            (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+        },
+    );
+    let margin = ((max_x - min_x).max(max_y - min_y) * 0.05).max(1.0);
+    let stroke = margin / 5.0;
+    let points = |line: &[(f64, f64)]| {
+        line.iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let waypoint = if waypoint.is_empty() {
+        String::new()
+    } else {
+        format!(
+            r##"<polyline fill="none" stroke="#999" stroke-width="{}" stroke-dasharray="{}" points="{}"/>
+"##,
+            stroke / 2.0,
+            stroke * 2.0,
+            points(&waypoint)
+        )
+    };
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">
+{}<polyline fill="none" stroke="#c33" stroke-width="{}" points="{}"/>
+<circle cx="0" cy="0" r="{}" fill="#33c"/>
+</svg>
+"##,
+        min_x - margin,
+        min_y - margin,
+        max_x - min_x + 2.0 * margin,
+        max_y - min_y + 2.0 * margin,
+        waypoint,
+        stroke,
+        points(&ship),
+        stroke * 2.0
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -217,4 +469,60 @@ mod tests {
         ship.apply_insts(&insts, Ship::apply_inst_wp).unwrap();
         assert_eq!(286, ship.manhattan_dist())
     }
+
+    #[test]
+    fn test_rotate() {
+        for &(degree, dir) in &[
+            (0, Direction::East),
+            (360, Direction::East),
+            (-450, Direction::South),
+            (630, Direction::South),
+            (180, Direction::West),
+        ] {
+            assert_eq!(dir, Direction::East.rotate(degree).unwrap());
+        }
+        assert!(Direction::East.rotate(45).is_err());
+        let mut wp = Waypoint::new();
+        wp.rotate(-720).unwrap();
+        assert_eq!((10, 1), (wp.x, wp.y));
+        wp.rotate(450).unwrap();
+        assert_eq!((-1, 10), (wp.x, wp.y));
+    }
+
+    #[test]
+    fn test_trace() {
+        let insts = Instruction::read(SAMPLE).unwrap();
+        let track = trace(&mut Ship::new(), &insts, Ship::apply_inst_wp, true).unwrap();
+        assert_eq!(insts.len() + 1, track.len());
+        assert_eq!((100.0, 10.0), track[1].ship);
+        assert_eq!((214.0, -72.0), track.last().unwrap().ship);
+        assert_eq!(Some((4.0, -10.0)), track.last().unwrap().waypoint);
+        assert!(to_csv(&track)
+            .starts_with("step,instruction,ship_x,ship_y,waypoint_x,waypoint_y\n0,,0,0,10,1\n"));
+        assert!(to_svg(&track).contains("stroke-dasharray"));
+
+        let float = trace(
+            &mut FloatShip::new(),
+            &insts,
+            FloatShip::apply_inst_wp,
+            true,
+        )
+        .unwrap();
+        for (exact, approx) in track.iter().zip(&float) {
+            assert!((exact.ship.0 - approx.ship.0).abs() < 1e-9);
+            assert!((exact.ship.1 - approx.ship.1).abs() < 1e-9);
+        }
+
+        let insts = Instruction::read("L45 F10 R135 F10").unwrap();
+        let track = trace(&mut FloatShip::new(), &insts, FloatShip::apply_inst, false).unwrap();
+        let (x, y) = track.last().unwrap().ship;
+        assert!((x - 50f64.sqrt()).abs() < 1e-9);
+        assert!((y - (50f64.sqrt() - 10.0)).abs() < 1e-9);
+
+        let csv = to_csv(&track);
+        assert!(csv.starts_with("step,instruction,ship_x,ship_y\n0,,0,0\n1,L45,0,0\n"));
+        let svg = to_svg(&track);
+        assert_eq!(1, svg.matches("<polyline").count());
+        assert!(!svg.contains("stroke-dasharray"));
+    }
 }
@@ -1,86 +1,341 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 type Error = Box<dyn std::error::Error>;
 
-fn main() {
+fn main() -> Result<(), Error> {
+    let mut verbose = false;
+    let mut next = None;
+    let mut at = None;
+    let mut align = None;
+    let mut window = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--verbose" => verbose = true,
+            "--next" => next = Some(usize::from_str(&value()?)?),
+            "--at" => at = Some(usize::from_str(&value()?)?),
+            "--align" => align = Some(parse_pairs(&value()?)?),
+            "--timetable" => {
+                let pair = parse_pairs(&value()?.replacen('-', ":", 1))?;
+                window = pair.first().cloned();
+            }
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+
     let input = include_str!("..\\input.txt");
-    let (timestamp, ids) = read(input).unwrap();
-    let (id, time) = find_lowest(timestamp, &ids).unwrap();
-    debug_assert_eq!(3035, id * (time - timestamp));
-    let stamps = read_stamps(input).unwrap();
-    debug_assert_eq!(725169163285238, find_repeat(&stamps).unwrap());
-    println!("All done")
+    let schedule = Schedule::from_str(input)?;
+    let (id, time) = schedule.earliest_bus()?;
+    debug_assert_eq!(3035, id * (time - schedule.timestamp));
+    println!("Earliest bus: {} at {}", id, time);
+    match find_repeat(&schedule.buses) {
+        Some(t) => {
+            debug_assert_eq!(725169163285238, t);
+            println!("Earliest timestamp: {}", t)
+        }
+        None => println!("No solution: bus offsets conflict or overflow"),
+    }
+    if verbose {
+        let congruences: Vec<_> = schedule
+            .buses
+            .iter()
+            .map(|&(offset, id)| Congruence::for_bus(offset, id))
+            .collect();
+        let mut steps = vec![];
+        if let Err(e) = solve_congruences(&congruences, &mut steps) {
+            steps.push(e.to_string());
+        }
+        steps.iter().for_each(|step| println!("{}", step));
+    }
+    if let Some(count) = next {
+        let time = at.unwrap_or(schedule.timestamp);
+        for (id, times) in schedule.departures(time, count) {
+            let times: Vec<_> = times.iter().map(|t| t.to_string()).collect();
+            println!("bus {}: {}", id, times.join(", "));
+        }
+    }
+    if let Some(picks) = align {
+        match schedule.alignment(&picks)? {
+            Some(t) => println!("Chosen buses line up at {}", t),
+            None => println!("Chosen buses never line up"),
+        }
+    }
+    if let Some((from, to)) = window {
+        print!("{}", schedule.timetable(from, to));
+    }
+    Ok(())
 }
 
-fn find_repeat(stamps: &[(usize, usize)]) -> Option<usize> {
-    let (rhs, modulii): (Vec<_>, Vec<_>) = stamps
-        .iter()
-        .map(|&(offset, stamp)| (-(offset as isize), stamp as isize))
-        .unzip();
-    match ring_algorithm::chinese_remainder_theorem(&rhs, &modulii) {
-        None => None,
-        Some(r) => {
-            if r >= 0 {
-                Some(r as usize)
-            } else {
-                let res = modulii.into_iter().fold(1, |acc, x| lcm(acc, x)) + r;
-                Some(res as usize)
+/// Parses `a:b,c:d` into `[(a, b), (c, d)]`.
+fn parse_pairs(s: &str) -> Result<Vec<(usize, usize)>, Error> {
+    s.split(',')
+        .map(|pair| {
+            let mut nums = pair.splitn(2, ':').map(|n| usize::from_str(n.trim()));
+            match (nums.next(), nums.next()) {
+                (Some(a), Some(b)) => Ok((a?, b?)),
+                _ => Err(format!("Expected a pair like 7:1, got {}", pair).into()),
             }
+        })
+        .collect()
+}
+
+/// `t = residue (mod modulus)`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Congruence {
+    residue: u128,
+    modulus: u128,
+}
+
+impl fmt::Display for Congruence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t = {} (mod {})", self.residue, self.modulus)
+    }
+}
+
+impl Congruence {
+    /// Bus `id` departing `offset` minutes after `t`.
+    fn for_bus(offset: usize, id: usize) -> Self {
+        let modulus = id as u128;
+        Self {
+            residue: (modulus - offset as u128 % modulus) % modulus,
+            modulus,
         }
     }
+
+    /// Combines two congruences into one modulo their lcm. Moduli need not be
+    /// coprime; `Ok(None)` means the two cannot hold at the same time.
+    fn merge(self, other: Self, steps: &mut Vec<String>) -> Result<Option<Self>, Error> {
+        let g = gcd(self.modulus, other.modulus);
+        let (lo, hi) = (
+            self.residue.min(other.residue),
+            self.residue.max(other.residue),
+        );
+        if (hi - lo) % g != 0 {
+            steps.push(format!(
+                "  {} and {} conflict: {} - {} is not divisible by gcd {}",
+                self, other, hi, lo, g
+            ));
+            return Ok(None);
+        }
+        let modulus = (self.modulus / g)
+            .checked_mul(other.modulus)
+            .ok_or_else(|| format!("Overflow merging {} and {}", self, other))?;
+        // t = a1 + m1 * k, so m1 * k = a2 - a1 (mod m2), divided through by g
+        let reduced = other.modulus / g;
+        let rhs = sub_mod(
+            other.residue % other.modulus,
+            self.residue % other.modulus,
+            other.modulus,
+        );
+        let diff = rhs / g;
+        let inv = mod_inv(self.modulus / g % reduced, reduced).unwrap_or(0);
+        let k = mul_mod(diff % reduced, inv, reduced);
+        let residue = add_mod(
+            self.residue % modulus,
+            mul_mod(self.modulus, k, modulus),
+            modulus,
+        );
+        let merged = Self { residue, modulus };
+        steps.push(format!(
+            "  t = {} + {}k, {}k = {} (mod {}) with gcd {} gives k = {} (mod {}), so {}",
+            self.residue, self.modulus, self.modulus, rhs, other.modulus, g, k, reduced, merged
+        ));
+        Ok(Some(merged))
+    }
 }
 
-fn read_stamps(input: &str) -> Result<Vec<(usize, usize)>, Error> {
-    let line = input
-        .split_terminator('\n')
-        .skip(1)
-        .next()
-        .ok_or_else(|| -> Error { "Insufficient input".into() })?;
-    let mut stamps: Vec<_> = line
-        .split(',')
-        .enumerate()
-        .filter_map(|(idx, s)| match usize::from_str(s.trim()) {
-            Ok(num) => Some((idx, num)),
-            _ => None,
-        })
+/// Folds all congruences into one, recording each step of the derivation.
+fn solve_congruences(
+    congruences: &[Congruence],
+    steps: &mut Vec<String>,
+) -> Result<Option<Congruence>, Error> {
+    let mut acc = Congruence {
+        residue: 0,
+        modulus: 1,
+    };
+    for &c in congruences {
+        steps.push(format!("Merge {} into {}", c, acc));
+        match acc.merge(c, steps)? {
+            Some(merged) => acc = merged,
+            None => return Ok(None),
+        }
+    }
+    steps.push(format!("Smallest t: {}", acc.residue));
+    Ok(Some(acc))
+}
+
+/// Earliest timestamp matching all offsets, `None` if they conflict or the
+/// answer does not fit in `u128`.
+fn find_repeat(stamps: &[(usize, usize)]) -> Option<u128> {
+    let congruences: Vec<_> = stamps
+        .iter()
+        .map(|&(offset, id)| Congruence::for_bus(offset, id))
         .collect();
-    stamps.sort_unstable_by_key(|(idx, _)| *idx);
-    Ok(stamps)
+    solve_congruences(&congruences, &mut vec![])
+        .ok()
+        .flatten()
+        .map(|c| c.residue)
 }
 
-fn find_lowest(threshold: usize, nums: &[usize]) -> Result<(usize, usize), Error> {
-    nums.into_iter()
-        .map(|&num| (num, (threshold / num + 1) * num))
-        .min_by_key(|(_num, higher_num)| *higher_num)
-        .ok_or_else(|| "Cannot find lowest number".into())
+/// Parsed puzzle input: the earliest time we can leave and every bus in
+/// service as `(offset, id)`, where `offset` is its position in the list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Schedule {
+    timestamp: usize,
+    buses: Vec<(usize, usize)>,
 }
 
-fn read(input: &str) -> Result<(usize, Vec<usize>), Error> {
-    let mut lines = input.split_terminator('\n');
-    let threshold = usize::from_str(
-        lines
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+        let timestamp = usize::from_str(lines.next().ok_or("Empty input")?)?;
+        let buses = lines
             .next()
-            .ok_or_else(|| -> Error { "Empty input".into() })?
-            .trim(),
-    )?;
-    let nums: Vec<_> = lines
-        .next()
-        .ok_or_else(|| -> Error { "Insufficient input".into() })?
-        .split(',')
-        .filter_map(|s| usize::from_str(s.trim()).ok())
-        .collect();
-    Ok((threshold, nums))
+            .ok_or("Insufficient input")?
+            .split(',')
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, s)| *s != "x")
+            .map(|(offset, s)| match usize::from_str(s) {
+                Ok(0) => Err(format!("Bus {} has ID 0", offset).into()),
+                Ok(id) => Ok((offset, id)),
+                Err(e) => Err(format!("Invalid bus {:?}: {}", s, e).into()),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self { timestamp, buses })
+    }
+}
+
+impl Schedule {
+    fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.buses.iter().map(|&(_, id)| id)
+    }
+
+    /// First departure of bus `id` at or after `time`.
+    fn next_departure(id: usize, time: usize) -> usize {
+        time.div_ceil(id) * id
+    }
+
+    /// The bus leaving soonest after `timestamp`, as `(id, departure)`.
+    fn earliest_bus(&self) -> Result<(usize, usize), Error> {
+        self.ids()
+            .map(|id| (id, Self::next_departure(id, self.timestamp)))
+            .min_by_key(|&(_, departure)| departure)
+            .ok_or_else(|| "Cannot find lowest number".into())
+    }
+
+    /// The next `count` departures of every bus at or after `time`.
+    fn departures(&self, time: usize, count: usize) -> Vec<(usize, Vec<usize>)> {
+        self.ids()
+            .map(|id| {
+                let first = Self::next_departure(id, time);
+                (id, (0..count).map(|i| first + i * id).collect())
+            })
+            .collect()
+    }
+
+    /// Earliest `t` at which each chosen bus `id` departs at `t + offset`.
+    fn alignment(&self, picks: &[(usize, usize)]) -> Result<Option<u128>, Error> {
+        let congruences = picks
+            .iter()
+            .map(|&(id, offset)| {
+                if self.ids().any(|bus| bus == id) {
+                    Ok(Congruence::for_bus(offset, id))
+                } else {
+                    Err(format!("Bus {} is not in service", id).into())
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(solve_congruences(&congruences, &mut vec![])?.map(|c| c.residue))
+    }
+
+    /// Departure board for `from..=to`, one row per minute and a `D` wherever
+    /// a bus departs, like the puzzle text draws it.
+    fn timetable(&self, from: usize, to: usize) -> String {
+        let headers: Vec<_> = self.ids().map(|id| format!("bus {}", id)).collect();
+        let time_width = to.to_string().len().max(4);
+        let mut table = format!("{:<w$}", "time", w = time_width);
+        for header in &headers {
+            table += &format!("  {}", header);
+        }
+        table.push('\n');
+        for time in from..=to {
+            table += &format!("{:<w$}", time, w = time_width);
+            for (id, header) in self.ids().zip(&headers) {
+                let mark = if time % id == 0 { 'D' } else { '.' };
+                table += &format!("  {:^w$}", mark, w = header.len());
+            }
+            table.truncate(table.trim_end().len());
+            table.push('\n');
+        }
+        table
+    }
 }
 
-fn gcd(x: isize, y: isize) -> isize {
+fn gcd(x: u128, y: u128) -> u128 {
     match y {
-        0 => x.abs(),
+        0 => x,
         _ => gcd(y, x % y),
     }
 }
 
-fn lcm(x: isize, y: isize) -> isize {
-    x * y / gcd(x, y)
+fn add_mod(a: u128, b: u128, m: u128) -> u128 {
+    if a >= m - b {
+        a - (m - b)
+    } else {
+        a + b
+    }
+}
+
+fn sub_mod(a: u128, b: u128, m: u128) -> u128 {
+    if a >= b {
+        a - b
+    } else {
+        m - (b - a)
+    }
+}
+
+/// `a * b % m` by doubling, so nothing overflows for any `m`.
+fn mul_mod(a: u128, b: u128, m: u128) -> u128 {
+    let (mut a, mut b) = (a % m, b);
+    let mut result = 0;
+    while b > 0 {
+        if b & 1 == 1 {
+            result = add_mod(result, a, m);
+        }
+        a = add_mod(a, a, m);
+        b >>= 1;
+    }
+    result
+}
+
+/// Extended Euclid with the Bezout coefficient kept modulo `m`.
+fn mod_inv(a: u128, m: u128) -> Option<u128> {
+    if m == 1 {
+        return Some(0);
+    }
+    let (mut t, mut new_t) = (0, 1);
+    let (mut r, mut new_r) = (m, a % m);
+    while new_r != 0 {
+        let q = r / new_r;
+        let next_t = sub_mod(t, mul_mod(q, new_t, m), m);
+        t = new_t;
+        new_t = next_t;
+        let next_r = r - q * new_r;
+        r = new_r;
+        new_r = next_r;
+    }
+    if r == 1 {
+        Some(t)
+    } else {
+        None
+    }
 }
 
 #[cfg(test)]
@@ -92,16 +347,16 @@ mod tests {
 
     #[test]
     fn test_find_lowest() {
-        let (timestamp, ids) = read(SAMPLE).unwrap();
-        let (id, time) = find_lowest(timestamp, &ids).unwrap();
+        let schedule = Schedule::from_str(SAMPLE).unwrap();
+        let (id, time) = schedule.earliest_bus().unwrap();
         assert_eq!(59, id);
-        assert_eq!(295, id * (time - timestamp))
+        assert_eq!(295, id * (time - schedule.timestamp))
     }
 
     #[test]
     fn test_find_repeat() {
         {
-            let stamps = read_stamps(SAMPLE).unwrap();
+            let stamps = Schedule::from_str(SAMPLE).unwrap().buses;
             assert_eq!(1068781, find_repeat(&stamps).unwrap())
         }
         {
@@ -153,4 +408,73 @@ mod tests {
             assert_eq!(1202161486, find_repeat(&stamps).unwrap())
         }
     }
+
+    #[test]
+    fn test_schedule_queries() {
+        let schedule = Schedule::from_str(SAMPLE).unwrap();
+        assert_eq!(
+            vec![(0, 7), (1, 13), (4, 59), (6, 31), (7, 19)],
+            schedule.buses
+        );
+        let departures = schedule.departures(939, 3);
+        assert_eq!((7, vec![945, 952, 959]), departures[0]);
+        assert_eq!((59, vec![944, 1003, 1062]), departures[2]);
+        // a bus leaving exactly at the requested time counts
+        assert_eq!(vec![938, 945], schedule.departures(938, 2)[0].1);
+
+        assert_eq!(
+            Some(1068781),
+            schedule
+                .alignment(&[(7, 0), (13, 1), (59, 4), (31, 6), (19, 7)])
+                .unwrap()
+        );
+        assert_eq!(Some(77), schedule.alignment(&[(7, 0), (13, 1)]).unwrap());
+        assert!(schedule.alignment(&[(8, 0)]).is_err());
+
+        let table = schedule.timetable(1068773, 1068782);
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!("time     bus 7  bus 13  bus 59  bus 31  bus 19", lines[0]);
+        assert_eq!("1068781    D      .       .       .       .", lines[9]);
+        assert_eq!("1068782    .      D       .       .       .", lines[10]);
+
+        assert!(Schedule::from_str("939\n7,x,0").is_err());
+        assert!(Schedule::from_str("939\n7,y").is_err());
+    }
+
+    #[test]
+    fn test_non_coprime() {
+        // 4 and 6 share a factor: t = 2 (mod 4) and t = 4 (mod 6) meet at 10
+        let buses = [Congruence::for_bus(2, 4), Congruence::for_bus(2, 6)];
+        let mut steps = vec![];
+        let merged = solve_congruences(&buses, &mut steps).unwrap().unwrap();
+        assert_eq!(
+            "  t = 2 + 4k, 4k = 2 (mod 6) with gcd 2 gives k = 2 (mod 3), so t = 10 (mod 12)",
+            steps[3]
+        );
+        assert_eq!(10, merged.residue);
+        assert_eq!(12, merged.modulus);
+        assert_eq!(Some(10), find_repeat(&[(2, 4), (2, 6)]));
+        // t even and t odd at once
+        assert_eq!(None, find_repeat(&[(0, 4), (1, 6)]));
+
+        // large primes whose product overflows u64 but not u128
+        let stamps = [(0, 4_294_967_291), (1, 4_294_967_279), (7, 4_294_967_231)];
+        let t = find_repeat(&stamps).unwrap();
+        assert!(t > u64::MAX as u128);
+        for &(offset, id) in &stamps {
+            assert_eq!(0, (t + offset as u128) % id as u128);
+        }
+        let overflow = [Congruence {
+            residue: 0,
+            modulus: u128::MAX,
+        }; 2];
+        let overflow = [
+            overflow[0],
+            Congruence {
+                modulus: 2,
+                ..overflow[1]
+            },
+        ];
+        assert!(solve_congruences(&overflow, &mut vec![]).is_err());
+    }
 }
@@ -1,111 +1,297 @@
-// Totally didn't do this by my self i.e take the code from others
-// Bit manipulation is not remotely the most interesting thing :(
-
 use std::{collections::HashMap, str::FromStr};
 
 type Error = Box<dyn std::error::Error>;
 
-fn main() {
-    let input = include_str!("..\\input.txt");
-    debug_assert_eq!(5875750429995, p1(input).unwrap());
-    debug_assert_eq!(5272149590143, p2(input).unwrap());
-    println!("All done")
-}
+fn main() -> Result<(), Error> {
+    let mut width = 36;
+    let mut decoders = vec![];
+    let mut trace = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--width" => width = u32::from_str(&args.next().ok_or("Missing width")?)?,
+            "--v1" => decoders.push(Decoder::V1),
+            "--v2" => decoders.push(Decoder::V2),
+            "--trace" => trace = true,
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+    if decoders.is_empty() {
+        decoders = vec![Decoder::V1, Decoder::V2];
+    }
 
-fn p1(input: &str) -> Result<usize, Error> {
-    let mut memory = HashMap::new();
-    let mut and_or = (0, 0);
-    for line in input.lines() {
-        let line = line.trim();
-        match line.strip_prefix("mask = ") {
-            Some(m) => {
-                and_or =
-                    m.bytes()
-                        .rev()
-                        .enumerate()
-                        .fold((usize::MAX, 0), |(and, or), (idx, byte)| match byte {
-                            b'0' => (and & !(1 << idx), or),
-                            b'1' => (and, or | 1 << idx),
-                            _ => (and, or),
-                        })
+    let input = include_str!("..\\input.txt");
+    let program = DockingProgram::parse(input, width)?;
+    for decoder in decoders {
+        let sum = program.run(decoder, |line, pattern, value| {
+            if trace {
+                println!(
+                    "line {}: mem[{}] = {}",
+                    line,
+                    pattern.format(program.width),
+                    value
+                );
             }
-            None => {
-                let (k, v) = read_value(line)?;
-                let value = v & and_or.0 | and_or.1;
-                memory.insert(k, value);
+        })?;
+        if width == 36 {
+            match decoder {
+                Decoder::V1 => debug_assert_eq!(5875750429995, sum),
+                Decoder::V2 => debug_assert_eq!(5272149590143, sum),
             }
         }
+        println!("{:?} sum: {}", decoder, sum);
+    }
+    Ok(())
+}
+
+/// One `mask = ...` line, bits set wherever the mask has that character.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct Mask {
+    zeros: u128,
+    ones: u128,
+    floating: u128,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Statement {
+    Mask(Mask),
+    Write { address: u128, value: u128 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DockingProgram {
+    width: u32,
+    /// Statements with their 1-based line numbers.
+    statements: Vec<(usize, Statement)>,
+}
+
+/// How a mask is applied to a write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Decoder {
+    /// The mask overwrites bits of the value.
+    V1,
+    /// The mask overwrites and floats bits of the address.
+    V2,
+}
+
+impl Decoder {
+    fn decode(&self, mask: &Mask, address: u128, value: u128) -> (AddressPattern, u128) {
+        match self {
+            Self::V1 => (
+                AddressPattern::new(address, 0),
+                value & !mask.zeros | mask.ones,
+            ),
+            Self::V2 => (
+                AddressPattern::new(address | mask.ones, mask.floating),
+                value,
+            ),
+        }
     }
-    Ok(memory.values().sum())
 }
 
-fn p2(input: &str) -> Result<usize, Error> {
-    let mut memory = HashMap::new();
-    let mut float_address = vec![];
-    let mut whitelist = 0;
-    for line in input.lines() {
-        let line = line.trim();
-        match line.strip_prefix("mask = ") {
-            Some(mask) => {
-                float_address.clear();
-                let mut float_base = 0;
-                let mut float_bits = vec![];
-                whitelist = 0;
-
-                mask.bytes()
-                    .rev()
-                    .enumerate()
-                    .for_each(|(idx, byte)| match byte {
-                        b'0' => whitelist |= 1 << idx,
-                        b'1' => float_base |= 1 << idx,
-                        b'X' => float_bits.push(idx),
-                        _ => unreachable!(),
-                    });
-                float_address = (0..2usize.pow(float_bits.len() as u32))
-                    .map(|template| {
-                        float_bits
-                            .iter()
-                            .enumerate()
-                            .fold(float_base, |addr, (idx, fb)| {
-                                addr | (template & 1 << idx) << fb - idx
-                            })
-                    })
-                    .collect()
+impl DockingProgram {
+    fn parse(input: &str, width: u32) -> Result<Self, Error> {
+        if width == 0 || width > 128 {
+            return Err(format!("Word width must be 1 to 128 bits, got {}", width).into());
+        }
+        let statements = input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(idx, line)| {
+                Self::parse_statement(line.trim(), width)
+                    .map(|statement| (idx + 1, statement))
+                    .map_err(|e| -> Error { format!("line {}: {}", idx + 1, e).into() })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { width, statements })
+    }
+
+    fn parse_statement(line: &str, width: u32) -> Result<Statement, Error> {
+        let mut sides = line.splitn(2, '=').map(str::trim);
+        let (lhs, rhs) = match (sides.next(), sides.next()) {
+            (Some(lhs), Some(rhs)) => (lhs, rhs),
+            _ => return Err(format!("Expected an assignment, got {:?}", line).into()),
+        };
+        if lhs == "mask" {
+            if rhs.len() != width as usize {
+                return Err(format!("Mask has {} bits, expected {}", rhs.len(), width).into());
             }
-            None => {
-                let (k, v) = read_value(line)?;
-                let key = k & whitelist;
-                for addr in float_address.as_slice() {
-                    memory.insert(key | addr, v);
+            let mut mask = Mask::default();
+            for (idx, ch) in rhs.chars().rev().enumerate() {
+                match ch {
+                    '0' => mask.zeros |= 1 << idx,
+                    '1' => mask.ones |= 1 << idx,
+                    'X' => mask.floating |= 1 << idx,
+                    _ => return Err(format!("Invalid mask character {:?}", ch).into()),
                 }
             }
+            return Ok(Statement::Mask(mask));
         }
+        let address = lhs
+            .strip_prefix("mem[")
+            .and_then(|s| s.strip_suffix(']'))
+            .ok_or_else(|| format!("Expected mask or mem[...], got {:?}", lhs))?;
+        let address = Self::parse_word(address, width)?;
+        let value = Self::parse_word(rhs, width)?;
+        Ok(Statement::Write { address, value })
     }
 
-    Ok(memory.values().sum())
+    fn parse_word(s: &str, width: u32) -> Result<u128, Error> {
+        let num = u128::from_str(s).map_err(|e| format!("Invalid number {:?}: {}", s, e))?;
+        if width < 128 && num >> width != 0 {
+            Err(format!("{} does not fit in {} bits", num, width).into())
+        } else {
+            Ok(num)
+        }
+    }
+
+    /// Runs the program and sums memory. `on_write` sees every write as its
+    /// line number, the addresses written and the value stored.
+    fn run<F>(&self, decoder: Decoder, mut on_write: F) -> Result<u128, Error>
+    where
+        F: FnMut(usize, &AddressPattern, u128),
+    {
+        let mut memory = FloatingMemory::default();
+        let mut mask = None;
+        for &(line, statement) in &self.statements {
+            match statement {
+                Statement::Mask(m) => mask = Some(m),
+                Statement::Write { address, value } => {
+                    let mask =
+                        mask.ok_or_else(|| format!("line {}: write before any mask", line))?;
+                    let (pattern, value) = decoder.decode(&mask, address, value);
+                    on_write(line, &pattern, value);
+                    memory.write(pattern, value);
+                }
+            }
+        }
+        memory.sum()
+    }
 }
 
-fn read_value(line: &str) -> Result<(usize, usize), Error> {
-    let mut kvpair = line.split(" = ");
-    let k = kvpair
-        .next()
-        .ok_or_else(|| -> Error { "Invalid value input".into() })?
-        .strip_prefix("mem[")
-        .ok_or_else(|| -> Error { "Invalid value input".into() })?
-        .strip_suffix(']')
-        .ok_or_else(|| -> Error { "Invalid value input".into() })?;
-    let key = usize::from_str(k)?;
-    let v = kvpair
-        .next()
-        .ok_or_else(|| -> Error { "Invalid value input".into() })?;
-    let value = usize::from_str(v)?;
-    Ok((key, value))
+/// A set of addresses: bits in `floating` take every value, all other bits
+/// are fixed to those in `bits`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct AddressPattern {
+    bits: u128,
+    floating: u128,
+}
+
+impl AddressPattern {
+    fn new(bits: u128, floating: u128) -> Self {
+        Self {
+            bits: bits & !floating,
+            floating,
+        }
+    }
+
+    /// Number of addresses, `None` if all 128 bits float.
+    fn len(&self) -> Option<u128> {
+        1u128.checked_shl(self.floating.count_ones())
+    }
+
+    fn format(&self, width: u32) -> String {
+        (0..width)
+            .rev()
+            .map(
+                |idx| match (self.floating >> idx & 1, self.bits >> idx & 1) {
+                    (1, _) => 'X',
+                    (_, 1) => '1',
+                    _ => '0',
+                },
+            )
+            .collect()
+    }
+
+    fn intersect(&self, other: &Self) -> Option<Self> {
+        let fixed = !self.floating & !other.floating;
+        if (self.bits ^ other.bits) & fixed != 0 {
+            None
+        } else {
+            Some(Self::new(
+                self.bits | other.bits,
+                self.floating & other.floating,
+            ))
+        }
+    }
+
+    /// Splits `self - other` into disjoint patterns: for every bit that floats
+    /// here but is fixed in `other`, one piece takes the opposite value there
+    /// while matching `other` on the bits handled before it.
+    fn subtract(&self, other: &Self) -> Vec<Self> {
+        if self.intersect(other).is_none() {
+            return vec![*self];
+        }
+        let mut pieces = vec![];
+        let mut current = *self;
+        let mut split = self.floating & !other.floating;
+        while split != 0 {
+            let bit = split & split.wrapping_neg();
+            split &= !bit;
+            let floating = current.floating & !bit;
+            pieces.push(Self::new(current.bits | (!other.bits & bit), floating));
+            current = Self::new(current.bits | (other.bits & bit), floating);
+        }
+        pieces
+    }
+}
+
+/// Memory holding disjoint address patterns, so that floating writes never
+/// need to be expanded into concrete addresses. Single addresses live in a
+/// plain map, which keeps programs without floating bits fast.
+#[derive(Debug, Clone, Default)]
+struct FloatingMemory {
+    exact: HashMap<u128, u128>,
+    regions: Vec<(AddressPattern, u128)>,
+}
+
+impl FloatingMemory {
+    fn write(&mut self, pattern: AddressPattern, value: u128) {
+        if !self.regions.is_empty() {
+            let mut regions = Vec::with_capacity(self.regions.len() + 1);
+            for (region, old) in self.regions.drain(..) {
+                regions.extend(region.subtract(&pattern).into_iter().map(|p| (p, old)));
+            }
+            self.regions = regions;
+        }
+        if pattern.floating == 0 {
+            self.exact.insert(pattern.bits, value);
+        } else {
+            self.exact
+                .retain(|&addr, _| pattern.intersect(&AddressPattern::new(addr, 0)).is_none());
+            self.regions.push((pattern, value));
+        }
+    }
+
+    fn sum(&self) -> Result<u128, Error> {
+        let overflow = || -> Error { "Memory sum overflows u128".into() };
+        let exact = self
+            .exact
+            .values()
+            .try_fold(0u128, |acc, &v| acc.checked_add(v))
+            .ok_or_else(overflow)?;
+        self.regions
+            .iter()
+            .try_fold(exact, |acc, (pattern, value)| {
+                acc.checked_add(pattern.len()?.checked_mul(*value)?)
+            })
+            .ok_or_else(overflow)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn p1(input: &str) -> Result<u128, Error> {
+        DockingProgram::parse(input, 36)?.run(Decoder::V1, |_, _, _| {})
+    }
+
+    fn p2(input: &str) -> Result<u128, Error> {
+        DockingProgram::parse(input, 36)?.run(Decoder::V2, |_, _, _| {})
+    }
+
     #[test]
     fn test_p1() {
         const SAMPLE: &str = r#"mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X
@@ -127,4 +313,74 @@ mod tests {
         let sum = p2(SAMPLE).unwrap();
         assert_eq!(208, sum)
     }
+
+    #[test]
+    fn test_p2_many_floating() {
+        // 2^36 addresses set to 1, then the lower half overwritten with 3
+        const SAMPLE: &str = r#"mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+        mem[0] = 1
+        mask = 0XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+        mem[0] = 3
+        mask = 0XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX1010
+        mem[5] = 0"#;
+
+        let sum = p2(SAMPLE).unwrap();
+        assert_eq!((1 << 35) + 3 * (1 << 35) - 3 * (1 << 31), sum);
+
+        let a = AddressPattern::new(0b0000, 0b1111);
+        let b = AddressPattern::new(0b0101, 0b0010);
+        let pieces = a.subtract(&b);
+        assert_eq!(3, pieces.len());
+        assert_eq!(14, pieces.iter().map(|p| p.len().unwrap()).sum::<u128>());
+        assert!(pieces.iter().all(|p| p.intersect(&b).is_none()));
+    }
+
+    #[test]
+    fn test_program() {
+        const SAMPLE: &str = r#"mask = X1X0
+        mem[3] = 1
+        mem[8] = 2"#;
+
+        let program = DockingProgram::parse(SAMPLE, 4).unwrap();
+        assert_eq!(3, program.statements.len());
+        let mut writes = vec![];
+        let sum = program
+            .run(Decoder::V2, |line, pattern, value| {
+                writes.push((line, pattern.format(4), value))
+            })
+            .unwrap();
+        assert_eq!(
+            vec![(2, "X1X1".to_string(), 1), (3, "X1X0".to_string(), 2)],
+            writes
+        );
+        assert_eq!(4 + 8, sum);
+        assert_eq!(
+            0b0100 + 0b0110,
+            program.run(Decoder::V1, |_, _, _| {}).unwrap()
+        );
+
+        let wide = format!("mask = 1{}\nmem[1] = 1", "X".repeat(99));
+        assert_eq!(
+            (1 << 99) | 1,
+            DockingProgram::parse(&wide, 100)
+                .unwrap()
+                .run(Decoder::V1, |_, _, _| {})
+                .unwrap()
+        );
+
+        let err = DockingProgram::parse("mask = X1X0\nmem[3] = 1\nmask = X2X0", 4).unwrap_err();
+        assert_eq!("line 3: Invalid mask character '2'", err.to_string());
+        let err = DockingProgram::parse("mask = X1X0\nmem[16] = 1", 4).unwrap_err();
+        assert_eq!("line 2: 16 does not fit in 4 bits", err.to_string());
+        assert!(DockingProgram::parse("mask = X1X0", 3).is_err());
+        assert!(DockingProgram::parse("mem[1] = 1", 4)
+            .unwrap()
+            .run(Decoder::V1, |_, _, _| {})
+            .is_err());
+        assert!(DockingProgram::parse("mask = X", 129).is_err());
+
+        let all_floating = format!("mask = {}\nmem[0] = 1", "X".repeat(128));
+        let program = DockingProgram::parse(&all_floating, 128).unwrap();
+        assert!(program.run(Decoder::V2, |_, _, _| {}).is_err());
+    }
 }
@@ -1,34 +1,171 @@
-use std::collections::HashMap;
-
-fn main() {
-    assert_eq!(1618, solve(&[0, 13, 1, 8, 6, 15], 2020));
-    assert_eq!(548531, solve(&[0, 13, 1, 8, 6, 15], 30000000));
-    println!("All done")
-}
-
-fn solve(input: &[usize], target_length: usize) -> usize {
-    let mut nums = Vec::with_capacity(target_length);
-    let mut positions = HashMap::new();
-    for (idx, num) in input.iter().enumerate() {
-        nums.push(*num);
-        positions.insert(*num, idx);
-    }
-    nums.push(0);
-    for i in input.len()..target_length {
-        let v = match positions.get(&nums[i]) {
-            Some(idx) => i - idx,
-            None => 0,
+use std::str::FromStr;
+
+type Error = Box<dyn std::error::Error>;
+
+fn main() -> Result<(), Error> {
+    let mut seeds = parse_seeds(include_str!(r#"..\input.txt"#))?;
+    let mut turns = vec![2020, 30000000];
+    let mut top = 0;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
         };
-        nums.push(v);
-        positions.insert(nums[i], i);
+        match arg.as_str() {
+            "--seeds" => seeds = parse_seeds(&value()?)?,
+            "--input" => seeds = parse_seeds(&std::fs::read_to_string(value()?)?)?,
+            "--turns" => {
+                turns = value()?
+                    .split(',')
+                    .map(|s| usize::from_str(s.trim()))
+                    .collect::<Result<_, _>>()?
+            }
+            "--stats" => top = usize::from_str(&value()?)?,
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+
+    let report = analyze(&seeds, &turns, top);
+    for &(turn, num) in &report.spoken {
+        if seeds == [0, 13, 1, 8, 6, 15] {
+            match turn {
+                2020 => assert_eq!(1618, num),
+                30000000 => assert_eq!(548531, num),
+                _ => (),
+            }
+        }
+        println!("Turn {}: {}", turn, num);
+    }
+    if top > 0 {
+        println!(
+            "{} distinct numbers, largest {}",
+            report.distinct, report.largest
+        );
+        for (num, count) in &report.most_spoken {
+            println!("  {} spoken {} times", num, count);
+        }
+    }
+    Ok(())
+}
+
+fn parse_seeds(s: &str) -> Result<Vec<u32>, Error> {
+    let seeds = s
+        .split(',')
+        .map(|n| u32::from_str(n.trim()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if seeds.is_empty() {
+        Err("No starting numbers".into())
+    } else {
+        Ok(seeds)
     }
-    nums[target_length - 1]
+}
+
+/// Endless stream of spoken numbers. Only the turn each number was last
+/// spoken on is kept, in a flat table indexed by the number itself.
+#[derive(Debug, Clone)]
+struct Game {
+    seeds: Vec<u32>,
+    /// 1-based turn a number was last spoken on, 0 if never.
+    last_seen: Vec<u32>,
+    turn: u32,
+    last: u32,
+}
+
+impl Game {
+    fn new(seeds: &[u32], capacity: usize) -> Self {
+        let largest = seeds.iter().max().map_or(0, |&n| n as usize + 1);
+        Self {
+            seeds: seeds.to_vec(),
+            last_seen: vec![0; capacity.max(largest)],
+            turn: 0,
+            last: 0,
+        }
+    }
+}
+
+impl Iterator for Game {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let spoken = match self.seeds.get(self.turn as usize) {
+            Some(&seed) => seed,
+            None => match self.last_seen[self.last as usize] {
+                0 => 0,
+                seen => self.turn - seen,
+            },
+        };
+        if self.turn > 0 {
+            self.last_seen[self.last as usize] = self.turn;
+        }
+        if spoken as usize >= self.last_seen.len() {
+            self.last_seen.resize(spoken as usize * 2, 0);
+        }
+        self.turn = self.turn.checked_add(1)?;
+        self.last = spoken;
+        Some(spoken)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Report {
+    /// `(turn, number)` for every requested turn, in ascending turn order.
+    spoken: Vec<(usize, u32)>,
+    /// Up to `top` `(number, times spoken)`, most frequent first.
+    most_spoken: Vec<(u32, u32)>,
+    distinct: usize,
+    largest: u32,
+}
+
+/// Plays once up to the furthest requested turn, picking up every requested
+/// turn on the way. Statistics are only gathered when `top` is non-zero.
+fn analyze(seeds: &[u32], turns: &[usize], top: usize) -> Report {
+    let mut wanted: Vec<_> = turns.iter().cloned().filter(|&t| t > 0).collect();
+    wanted.sort_unstable();
+    wanted.dedup();
+    let last = wanted.last().cloned().unwrap_or(0);
+    let mut counts = vec![];
+    let mut report = Report::default();
+    let mut next = wanted.iter().peekable();
+    for (idx, num) in Game::new(seeds, last).take(last).enumerate() {
+        if next.peek() == Some(&&(idx + 1)) {
+            report.spoken.push((idx + 1, num));
+            next.next();
+        }
+        if top > 0 {
+            if num as usize >= counts.len() {
+                counts.resize(num as usize + 1, 0u32);
+            }
+            counts[num as usize] += 1;
+        }
+    }
+    if top > 0 {
+        report.largest = counts.len().saturating_sub(1) as u32;
+        report.distinct = counts.iter().filter(|&&c| c > 0).count();
+        let mut by_count: Vec<_> = counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &c)| c > 0)
+            .map(|(num, &c)| (num as u32, c))
+            .collect();
+        by_count.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        by_count.truncate(top);
+        report.most_spoken = by_count;
+    }
+    report
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn solve(input: &[usize], target_length: usize) -> usize {
+        let seeds: Vec<_> = input.iter().map(|&n| n as u32).collect();
+        Game::new(&seeds, target_length)
+            .nth(target_length - 1)
+            .unwrap() as usize
+    }
+
     #[test]
     fn test_p1() {
         assert_eq!(436, solve(&[0, 3, 6], 2020));
@@ -50,4 +187,20 @@ mod tests {
         assert_eq!(18, solve(&[3, 2, 1], 30000000));
         assert_eq!(362, solve(&[3, 1, 2], 30000000));
     }
+
+    #[test]
+    fn test_analyze() {
+        assert_eq!(
+            vec![0, 3, 6, 0, 3, 3, 1, 0, 4, 0],
+            Game::new(&[0, 3, 6], 0).take(10).collect::<Vec<_>>()
+        );
+        let report = analyze(&[0, 3, 6], &[2020, 4, 10, 4], 2);
+        assert_eq!(vec![(4, 0), (10, 0), (2020, 436)], report.spoken);
+        assert_eq!(0, report.most_spoken[0].0);
+        assert_eq!(
+            Ok(vec![0, 13, 1]),
+            parse_seeds(" 0, 13,1\n").map_err(|e| e.to_string())
+        );
+        assert!(parse_seeds("0,,1").is_err());
+    }
 }
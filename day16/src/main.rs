@@ -1,125 +1,339 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::RangeInclusive,
+};
 
 type Error = Box<dyn std::error::Error>;
 
-fn main() {
+fn main() -> Result<(), Error> {
+    let mut prefix = "departure".to_string();
+    let mut show_report = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--prefix" => prefix = args.next().ok_or("Missing prefix")?,
+            "--report" => show_report = true,
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+
     let input = include_str!("..\\input.txt");
-    let (rules, ticket, nearby) = parse(input).unwrap();
-    assert_eq!(23044, scan_error_rate(&rules, &nearby));
-    assert_eq!(3765150732757, p2(&rules, &ticket, &nearby));
-    println!("All done")
+    let (rules, ticket, nearby) = parse(input)?;
+    let error_rate = scan_error_rate(&rules, &nearby);
+    assert_eq!(23044, error_rate);
+    println!("Error rate: {}", error_rate);
+    let product = p2(&rules, &ticket, &nearby, &prefix)?;
+    if prefix == "departure" {
+        assert_eq!(3765150732757, product);
+    }
+    println!("Product of {}* fields: {}", prefix, product);
+    if show_report {
+        let report = Report::new(&rules, &ticket, &nearby)?;
+        assert_eq!(23044, report.invalid.iter().map(|v| v.value).sum::<u32>());
+        for invalid in &report.invalid {
+            println!(
+                "nearby ticket {} position {}: {} fits no rule",
+                invalid.ticket, invalid.position, invalid.value
+            );
+        }
+        println!("your ticket: {}", format_decoded(&report.yours));
+        for (idx, decoded) in &report.decoded {
+            println!("nearby ticket {}: {}", idx, format_decoded(decoded));
+        }
+    }
+    Ok(())
+}
+
+fn format_decoded(decoded: &BTreeMap<&str, u32>) -> String {
+    decoded
+        .iter()
+        .map(|(field, value)| format!("{}={}", field, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule<'a> {
+    field: &'a str,
+    ranges: Vec<RangeInclusive<u32>>,
+}
+
+impl Rule<'_> {
+    fn accepts(&self, value: u32) -> bool {
+        self.ranges.iter().any(|range| range.contains(&value))
+    }
 }
 
-fn p2(
-    rules: &HashMap<&str, (u32, u32, u32, u32)>,
-    ticket: &[u32],
-    nearby: &Vec<Vec<u32>>,
-) -> usize {
+/// Field name to position on the ticket.
+type Fields<'a> = HashMap<&'a str, usize>;
+
+fn format_fields(fields: &Fields) -> String {
+    let mut pairs: Vec<_> = fields.iter().collect();
+    pairs.sort_by_key(|&(field, &idx)| (idx, *field));
+    pairs
+        .iter()
+        .map(|(field, idx)| format!("{}={}", field, idx))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Assignment<'a> {
+    Unique(Fields<'a>),
+    /// More than one assignment fits; holds the ones found, up to the
+    /// requested limit.
+    Ambiguous(Vec<Fields<'a>>),
+    Impossible,
+}
+
+/// Product of the fields on `ticket` whose name starts with `prefix`.
+fn p2(rules: &[Rule], ticket: &[u32], nearby: &[Vec<u32>], prefix: &str) -> Result<usize, Error> {
+    let fields = unique_fields(rules, nearby)?;
+    Ok(decode(&fields, ticket)
+        .iter()
+        .filter(|(field, _)| field.starts_with(prefix))
+        .map(|(_, &value)| value as usize)
+        .product())
+}
+
+fn unique_fields<'a>(rules: &[Rule<'a>], nearby: &[Vec<u32>]) -> Result<Fields<'a>, Error> {
     let valid_tickets = find_valid_tickets(rules, nearby);
-    let fields = match_fields(rules, &valid_tickets);
+    match match_fields(rules, &valid_tickets, 2) {
+        Assignment::Unique(fields) => Ok(fields),
+        Assignment::Ambiguous(all) => Err(format!(
+            "Ambiguous field assignment, at least:\n{}",
+            all.iter()
+                .map(|fields| format!("  {}", format_fields(fields)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+        .into()),
+        Assignment::Impossible => Err(format!(
+            "No valid field assignment, positions each field fits:\n{}",
+            rules
+                .iter()
+                .zip(candidate_positions(rules, &valid_tickets))
+                .map(|(rule, positions)| format!("  {}={:?}", rule.field, positions))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+        .into()),
+    }
+}
+
+fn decode<'a>(fields: &Fields<'a>, ticket: &[u32]) -> BTreeMap<&'a str, u32> {
     fields
         .iter()
-        .filter_map(|(field, idx)| match field.starts_with("departure") {
-            true => Some(idx),
-            false => None,
+        .filter_map(|(&field, &idx)| ticket.get(idx).map(|&value| (field, value)))
+        .collect()
+}
+
+/// A value on a nearby ticket that no rule accepts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct InvalidValue {
+    ticket: usize,
+    position: usize,
+    value: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Report<'a> {
+    invalid: Vec<InvalidValue>,
+    yours: BTreeMap<&'a str, u32>,
+    /// Every valid nearby ticket by its index in the notes.
+    decoded: Vec<(usize, BTreeMap<&'a str, u32>)>,
+}
+
+impl<'a> Report<'a> {
+    fn new(rules: &[Rule<'a>], ticket: &[u32], nearby: &[Vec<u32>]) -> Result<Self, Error> {
+        let fields = unique_fields(rules, nearby)?;
+        let decoded = nearby
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| check_invalid_ticket(rules, t).is_empty())
+            .map(|(idx, t)| (idx, decode(&fields, t)))
+            .collect();
+        Ok(Self {
+            invalid: invalid_values(rules, nearby),
+            yours: decode(&fields, ticket),
+            decoded,
         })
-        .map(|idx| ticket[*idx] as usize)
-        .product()
+    }
 }
 
+/// Assigns every rule a distinct ticket position whose values it accepts on
+/// all `valid_tickets`, by backtracking over the most constrained field first.
+/// Each branch is checked with an augmenting-path matching before descending,
+/// so no time is spent on dead ends. Stops after `limit` assignments.
 fn match_fields<'a>(
-    rules: &'a HashMap<&'a str, (u32, u32, u32, u32)>,
-    valid_tickets: &'a Vec<Vec<u32>>,
-) -> HashMap<&'a str, usize> {
-    let length = valid_tickets[0].len();
-    let mut tmp: HashMap<&str, Vec<_>> = HashMap::new();
-
-    for (field, limit) in rules {
-        for idx in 0..length {
-            if valid_tickets.iter().all(|ticket| {
-                let value = ticket[idx];
-                (limit.0 <= value && value <= limit.1) || (limit.2 <= value && value <= limit.3)
-            }) {
-                match tmp.get_mut(field) {
-                    Some(v) => v.push(idx),
-                    None => {
-                        tmp.insert(*field, vec![idx]);
-                    }
-                }
-            }
+    rules: &[Rule<'a>],
+    valid_tickets: &[Vec<u32>],
+    limit: usize,
+) -> Assignment<'a> {
+    let candidates = candidate_positions(rules, valid_tickets);
+    let mut taken = vec![None; rules.len()];
+    let mut found = vec![];
+    if has_matching(&candidates, &taken) {
+        backtrack(&candidates, &mut taken, &mut found, limit.max(1));
+    }
+    let mut assignments: Vec<Fields> = found
+        .into_iter()
+        .map(|positions| {
+            rules
+                .iter()
+                .zip(positions)
+                .map(|(rule, idx)| (rule.field, idx))
+                .collect()
+        })
+        .collect();
+    match assignments.len() {
+        0 => Assignment::Impossible,
+        1 => Assignment::Unique(assignments.remove(0)),
+        _ => Assignment::Ambiguous(assignments),
+    }
+}
+
+/// Positions whose values each rule accepts on all `valid_tickets`.
+fn candidate_positions(rules: &[Rule], valid_tickets: &[Vec<u32>]) -> Vec<Vec<usize>> {
+    let length = valid_tickets
+        .first()
+        .map_or(rules.len(), |ticket| ticket.len());
+    rules
+        .iter()
+        .map(|rule| {
+            (0..length)
+                .filter(|&idx| valid_tickets.iter().all(|ticket| rule.accepts(ticket[idx])))
+                .collect()
+        })
+        .collect()
+}
+
+fn backtrack(
+    candidates: &[Vec<usize>],
+    taken: &mut Vec<Option<usize>>,
+    found: &mut Vec<Vec<usize>>,
+    limit: usize,
+) {
+    let used = |taken: &[Option<usize>], idx: usize| taken.contains(&Some(idx));
+    let next = (0..candidates.len())
+        .filter(|&field| taken[field].is_none())
+        .min_by_key(|&field| {
+            candidates[field]
+                .iter()
+                .filter(|&&idx| !used(taken, idx))
+                .count()
+        });
+    let field = match next {
+        Some(field) => field,
+        None => {
+            found.push(taken.iter().map(|t| t.unwrap()).collect());
+            return;
+        }
+    };
+    for &idx in &candidates[field] {
+        if found.len() >= limit {
+            return;
+        }
+        if used(taken, idx) {
+            continue;
+        }
+        taken[field] = Some(idx);
+        if has_matching(candidates, taken) {
+            backtrack(candidates, taken, found, limit);
         }
+        taken[field] = None;
     }
+}
 
-    let mut fields = HashMap::new();
-
-    while fields.len() < tmp.len() {
-        for (k, v) in tmp.iter_mut() {
-            if v.len() == 1 {
-                fields.insert(*k, v[0]);
-            } else {
-                for i in fields.values() {
-                    match v.iter().position(|x| *x == *i) {
-                        Some(pos) => {
-                            v.remove(pos);
-                        }
-                        None => (),
-                    }
-                }
+/// Whether the fields not yet in `taken` can still all get a distinct free
+/// position (Kuhn's augmenting paths).
+fn has_matching(candidates: &[Vec<usize>], taken: &[Option<usize>]) -> bool {
+    fn augment(
+        field: usize,
+        candidates: &[Vec<usize>],
+        owner: &mut HashMap<usize, usize>,
+        visited: &mut Vec<usize>,
+    ) -> bool {
+        for &idx in &candidates[field] {
+            if visited.contains(&idx) {
+                continue;
+            }
+            visited.push(idx);
+            let free = match owner.get(&idx) {
+                None => true,
+                Some(&other) => other != usize::MAX && augment(other, candidates, owner, visited),
+            };
+            if free {
+                owner.insert(idx, field);
+                return true;
             }
         }
+        false
     }
 
-    fields
+    // positions already handed out are pinned with a sentinel owner
+    let mut owner: HashMap<usize, usize> = taken
+        .iter()
+        .filter_map(|&t| t.map(|idx| (idx, usize::MAX)))
+        .collect();
+    (0..candidates.len())
+        .filter(|&field| taken[field].is_none())
+        .all(|field| augment(field, candidates, &mut owner, &mut vec![]))
 }
 
-fn find_valid_tickets(
-    rules: &HashMap<&str, (u32, u32, u32, u32)>,
-    nearby: &Vec<Vec<u32>>,
-) -> Vec<Vec<u32>> {
+fn find_valid_tickets(rules: &[Rule], nearby: &[Vec<u32>]) -> Vec<Vec<u32>> {
     nearby
         .iter()
-        .filter(|ticket| check_invalid_ticket(rules, ticket).is_none())
+        .filter(|ticket| check_invalid_ticket(rules, ticket).is_empty())
         .cloned()
         .collect()
 }
 
-fn check_invalid_ticket(
-    rules: &HashMap<&str, (u32, u32, u32, u32)>,
-    ticket: &[u32],
-) -> Option<u32> {
-    for &value in ticket {
-        if rules
-            .values()
-            .all(|limit| value < limit.0 || limit.3 < value || (limit.1 < value && value < limit.2))
-        {
-            return Some(value);
-        }
-    }
-    None
+/// Every `(position, value)` on the ticket that no rule accepts.
+fn check_invalid_ticket(rules: &[Rule], ticket: &[u32]) -> Vec<(usize, u32)> {
+    ticket
+        .iter()
+        .cloned()
+        .enumerate()
+        .filter(|&(_, value)| !rules.iter().any(|rule| rule.accepts(value)))
+        .collect()
 }
 
-fn scan_error_rate(rules: &HashMap<&str, (u32, u32, u32, u32)>, nearby: &Vec<Vec<u32>>) -> u32 {
+fn invalid_values(rules: &[Rule], nearby: &[Vec<u32>]) -> Vec<InvalidValue> {
     nearby
         .iter()
-        .filter_map(|ticket| check_invalid_ticket(rules, ticket))
-        .sum()
+        .enumerate()
+        .flat_map(|(idx, ticket)| {
+            check_invalid_ticket(rules, ticket)
+                .into_iter()
+                .map(move |(position, value)| InvalidValue {
+                    ticket: idx,
+                    position,
+                    value,
+                })
+        })
+        .collect()
+}
+
+fn scan_error_rate(rules: &[Rule], nearby: &[Vec<u32>]) -> u32 {
+    invalid_values(rules, nearby).iter().map(|v| v.value).sum()
 }
 
-fn parse(
-    input: &str,
-) -> Result<(HashMap<&str, (u32, u32, u32, u32)>, Vec<u32>, Vec<Vec<u32>>), Error> {
-    let rules: HashMap<_, _> = input
+type Notes<'a> = (Vec<Rule<'a>>, Vec<u32>, Vec<Vec<u32>>);
+
+fn parse(input: &str) -> Result<Notes<'_>, Error> {
+    let rules = input
         .lines()
         .take_while(|line| !line.trim().is_empty())
         .map(parse_rule)
-        .collect::<Result<HashMap<_, _>, Error>>()?;
+        .collect::<Result<Vec<_>, Error>>()?;
     let ticket = input
         .lines()
         .skip_while(|line| !line.trim().starts_with("your ticket"))
         .skip(1)
         .map(parse_ticket)
         .next()
-        .ok_or_else(|| "Invalid ticket input")??;
+        .ok_or("Invalid ticket input")??;
     let nearby = input
         .lines()
         .skip_while(|line| !line.trim().starts_with("nearby tickets"))
@@ -131,18 +345,25 @@ fn parse(
     Ok((rules, ticket, nearby))
 }
 
-fn parse_rule(line: &str) -> Result<(&str, (u32, u32, u32, u32)), Error> {
-    let mut kvpair = line.split(':');
-    let field = kvpair.next().ok_or_else(|| "Invalid rule input")?.trim();
-    let limits = kvpair
+fn parse_rule(line: &str) -> Result<Rule<'_>, Error> {
+    let mut kvpair = line.splitn(2, ':');
+    let field = kvpair.next().ok_or("Invalid rule input")?.trim();
+    let ranges = kvpair
         .next()
-        .ok_or_else(|| "Invalid rule input")?
-        .trim()
-        .split("or")
-        .map(|pair| pair.trim().split('-').map(|num| num.parse()))
-        .flatten()
+        .ok_or("Invalid rule input")?
+        .split(" or ")
+        .map(|pair| -> Result<_, Error> {
+            let mut nums = pair
+                .trim()
+                .splitn(2, '-')
+                .map(|num| num.trim().parse::<u32>());
+            match (nums.next(), nums.next()) {
+                (Some(lo), Some(hi)) => Ok(lo?..=hi?),
+                _ => Err(format!("Invalid range {:?} in rule {}", pair, field).into()),
+            }
+        })
         .collect::<Result<Vec<_>, _>>()?;
-    Ok((field, (limits[0], limits[1], limits[2], limits[3])))
+    Ok(Rule { field, ranges })
 }
 
 fn parse_ticket(line: &str) -> Result<Vec<u32>, Error> {
@@ -191,6 +412,122 @@ mod tests {
     #[test]
     fn test_match_fields() {
         let (rules, _, nearby) = parse(SAMPLE2).unwrap();
-        println!("{:?}", match_fields(&rules, &nearby))
+        let expected: Fields = vec![("row", 0), ("class", 1), ("seat", 2)]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            Assignment::Unique(expected),
+            match_fields(&rules, &nearby, 10)
+        );
+    }
+
+    #[test]
+    fn test_match_fields_edge_cases() {
+        // a single range and three ranges
+        let (rules, _, nearby) = parse(
+            r#"low: 0-4
+            odd: 1-1 or 3-3 or 5-5
+
+            your ticket:
+            1,2
+
+            nearby tickets:
+            2,3
+            4,5"#,
+        )
+        .unwrap();
+        assert_eq!(vec![0..=4], rules[0].ranges);
+        assert_eq!(3, rules[1].ranges.len());
+        match match_fields(&rules, &nearby, 10) {
+            Assignment::Unique(fields) => {
+                assert_eq!(0, fields["low"]);
+                assert_eq!(1, fields["odd"]);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // both fields fit both positions
+        let (rules, _, nearby) = parse(
+            r#"a: 0-9
+            b: 0-9
+
+            your ticket:
+            1,2
+
+            nearby tickets:
+            3,4"#,
+        )
+        .unwrap();
+        match match_fields(&rules, &nearby, 10) {
+            Assignment::Ambiguous(all) => assert_eq!(2, all.len()),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            "Ambiguous field assignment, at least:\n  a=0, b=1\n  b=0, a=1",
+            p2(&rules, &[1, 2], &nearby, "a").unwrap_err().to_string()
+        );
+
+        // both a and b only fit the first position
+        let (rules, _, nearby) = parse(
+            r#"a: 0-5
+            b: 0-5
+            c: 9-9
+
+            your ticket:
+            1,9
+
+            nearby tickets:
+            3,9"#,
+        )
+        .unwrap();
+        assert_eq!(Assignment::Impossible, match_fields(&rules, &nearby, 10));
+        assert_eq!(
+            "No valid field assignment, positions each field fits:\n  a=[0]\n  b=[0]\n  c=[1]",
+            Report::new(&rules, &[1, 9], &nearby)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let (rules, ticket, nearby) = parse(SAMPLE2).unwrap();
+        assert_eq!(12, p2(&rules, &ticket, &nearby, "c").unwrap());
+        assert_eq!(12 * 11 * 13, p2(&rules, &ticket, &nearby, "").unwrap());
+
+        let report = Report::new(&rules, &ticket, &nearby).unwrap();
+        assert!(report.invalid.is_empty());
+        assert_eq!(
+            vec![0, 1, 2],
+            report
+                .decoded
+                .iter()
+                .map(|(idx, _)| *idx)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(15, report.decoded[1].1["row"]);
+        assert_eq!("class=12, row=11, seat=13", format_decoded(&report.yours));
+
+        // 0 fits no rule and the last ticket has two bad values
+        let (rules, _, mut nearby) = parse(SAMPLE1).unwrap();
+        nearby.push(vec![0, 7, 47]);
+        nearby.push(vec![51, 7, 4]);
+        let found: Vec<_> = invalid_values(&rules, &nearby)
+            .iter()
+            .map(|v| (v.ticket, v.position, v.value))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, 1, 4),
+                (2, 0, 55),
+                (3, 2, 12),
+                (4, 0, 0),
+                (5, 0, 51),
+                (5, 2, 4)
+            ],
+            found
+        );
+        assert_eq!(71 + 51 + 4, scan_error_rate(&rules, &nearby));
+        assert_eq!(1, find_valid_tickets(&rules, &nearby).len());
     }
 }
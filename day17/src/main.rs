@@ -1,256 +1,595 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::{BuildHasherDefault, Hasher},
+    str::FromStr,
+};
 
-use itertools::Itertools;
+type Error = Box<dyn std::error::Error>;
+
+fn main() -> Result<(), Error> {
+    let mut dims = vec![];
+    let mut cycles = 6;
+    let mut rule = LifeRule::default();
+    let mut symmetric = false;
+    let mut render = false;
+    let mut csv_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--dims" => dims.push(value()?.parse()?),
+            "--cycles" => cycles = value()?.parse()?,
+            "--rule" => rule = value()?.parse()?,
+            "--symmetric" => symmetric = true,
+            "--render" => render = true,
+            "--csv" => csv_path = Some(value()?),
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+    if dims.is_empty() {
+        dims = vec![3, 4];
+    }
+    if csv_path.is_some() && dims.len() > 1 {
+        return Err("--csv needs a single --dims".into());
+    }
 
-fn main() {
     let input = include_str!("..\\input.txt");
-    assert_eq!(237, p1::run(input));
-    assert_eq!(2448, p2::run(input));
-    println!("All done")
+    let conway = LifeRule::default();
+    assert_eq!(237, run::<3>(input, 6, &conway, false, None)?);
+    assert_eq!(2448, run::<4>(input, 6, &conway, false, None)?);
+    let mut csv = String::new();
+    for dim in dims {
+        let mut on_cycle = |cycle: usize, cells: &[Vec<i32>]| {
+            if render {
+                match cycle {
+                    0 => println!("Before any cycles:\n"),
+                    1 => println!("After 1 cycle:\n"),
+                    _ => println!("After {} cycles:\n", cycle),
+                }
+                println!("{}", render_slices(cells));
+            }
+            if csv_path.is_some() {
+                if csv.is_empty() {
+                    csv.push_str(&csv_header(dim));
+                }
+                csv.push_str(&csv_rows(cycle, cells));
+            }
+        };
+        let observer: Option<Observer> = if render || csv_path.is_some() {
+            Some(&mut on_cycle)
+        } else {
+            None
+        };
+        let active = run_dims(input, dim, cycles, &rule, symmetric, observer)?;
+        println!(
+            "{}D {} after {} cycles: {} active",
+            dim, rule, cycles, active
+        );
+    }
+    if let Some(path) = csv_path {
+        std::fs::write(path, csv)?;
+    }
+    Ok(())
 }
 
-mod p1 {
-    use super::*;
+/// Called with the full set of active cells before the first cycle and after
+/// every cycle. Without one the cell list is never built.
+type Observer<'a> = &'a mut dyn FnMut(usize, &[Vec<i32>]);
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-    struct Coord {
-        x: i32,
-        y: i32,
-        z: i32,
+const AXES: [&str; 8] = ["x", "y", "z", "w", "v", "u", "t", "s"];
+
+/// Prints the cells as `z=…, w=…` slices like the puzzle text, all cropped to
+/// the bounding box of the active cells.
+fn render_slices(cells: &[Vec<i32>]) -> String {
+    let dims = match cells.first() {
+        Some(cell) => cell.len(),
+        None => return "(empty)\n".to_string(),
+    };
+    let min: Vec<_> = (0..dims)
+        .map(|axis| cells.iter().map(|c| c[axis]).min().unwrap())
+        .collect();
+    let max: Vec<_> = (0..dims)
+        .map(|axis| cells.iter().map(|c| c[axis]).max().unwrap())
+        .collect();
+    let active: HashSet<&[i32]> = cells.iter().map(|c| c.as_slice()).collect();
+
+    let mut out = String::new();
+    // odometer over the extra dimensions, with z turning fastest
+    let mut slice = min.clone();
+    loop {
+        if dims > 2 {
+            let header: Vec<_> = (2..dims)
+                .map(|axis| format!("{}={}", AXES[axis], slice[axis]))
+                .collect();
+            out.push_str(&header.join(", "));
+            out.push('\n');
+        }
+        let mut cell = slice.clone();
+        for y in min[1]..=max[1] {
+            cell[1] = y;
+            for x in min[0]..=max[0] {
+                cell[0] = x;
+                out.push(if active.contains(cell.as_slice()) {
+                    '#'
+                } else {
+                    '.'
+                });
+            }
+            out.push('\n');
+        }
+        match (2..dims).find(|&axis| slice[axis] < max[axis]) {
+            Some(axis) => {
+                slice[axis] += 1;
+                slice[2..axis].copy_from_slice(&min[2..axis]);
+                out.push('\n');
+            }
+            None => return out,
+        }
     }
+}
+
+fn csv_header(dims: usize) -> String {
+    format!("cycle,{}\n", AXES[..dims].join(","))
+}
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    enum State {
-        Active,
-        Inactive,
+fn csv_rows(cycle: usize, cells: &[Vec<i32>]) -> String {
+    cells
+        .iter()
+        .map(|cell| {
+            let coords: Vec<_> = cell.iter().map(|c| c.to_string()).collect();
+            format!("{},{}\n", cycle, coords.join(","))
+        })
+        .collect()
+}
+
+/// Runs the simulation in `dims` dimensions, which must be between 2 and 8.
+fn run_dims(
+    input: &str,
+    dims: usize,
+    cycles: usize,
+    rule: &LifeRule,
+    symmetric: bool,
+    on_cycle: Option<Observer>,
+) -> Result<usize, Error> {
+    match dims {
+        2 => run::<2>(input, cycles, rule, symmetric, on_cycle),
+        3 => run::<3>(input, cycles, rule, symmetric, on_cycle),
+        4 => run::<4>(input, cycles, rule, symmetric, on_cycle),
+        5 => run::<5>(input, cycles, rule, symmetric, on_cycle),
+        6 => run::<6>(input, cycles, rule, symmetric, on_cycle),
+        7 => run::<7>(input, cycles, rule, symmetric, on_cycle),
+        8 => run::<8>(input, cycles, rule, symmetric, on_cycle),
+        _ => Err(format!("Unsupported dimension {}", dims).into()),
     }
+}
+
+/// Life-like rule in B/S notation: a dead cell with a neighbor count listed
+/// under `B` is born, a live one with a count under `S` survives. Counts above
+/// 9 are written comma separated, e.g. `B3/S2,3,10`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LifeRule {
+    birth: Vec<u32>,
+    survive: Vec<u32>,
+}
 
-    pub fn run(input: &str) -> usize {
-        let init = read(input);
-        let last = simulate(init, 0, 6);
-        last.values().filter(|s| **s == State::Active).count()
+impl Default for LifeRule {
+    fn default() -> Self {
+        Self {
+            birth: vec![3],
+            survive: vec![2, 3],
+        }
     }
+}
 
-    fn simulate(
-        old_state: HashMap<Coord, State>,
-        current_cycle: u32,
-        target_cycle: u32,
-    ) -> HashMap<Coord, State> {
-        if current_cycle == target_cycle {
-            old_state
+impl LifeRule {
+    fn next(&self, alive: bool, count: u32) -> bool {
+        if alive {
+            self.survive.contains(&count)
         } else {
-            let coords: Vec<_> = old_state.keys().collect();
-            let new_coords = apply_deltas(&coords);
-            let new_state = new_coords
-                .into_iter()
-                .map(|new_coord| {
-                    let active_count = apply_delta(&new_coord)
-                        .into_iter()
-                        .filter(|c| *c != new_coord)
-                        .filter(|c| match old_state.get(&c) {
-                            Some(State::Active) => true,
-                            _ => false,
-                        })
-                        .count();
-                    match old_state.get(&new_coord) {
-                        Some(State::Active) => match active_count {
-                            2 | 3 => (new_coord, State::Active),
-                            _ => (new_coord, State::Inactive),
-                        },
-                        _ => match active_count {
-                            3 => (new_coord, State::Active),
-                            _ => (new_coord, State::Inactive),
-                        },
-                    }
-                })
-                .collect();
-            simulate(new_state, current_cycle + 1, target_cycle)
+            self.birth.contains(&count)
         }
     }
+}
 
-    fn read(input: &str) -> HashMap<Coord, State> {
-        input
-            .lines()
-            .enumerate()
-            .map(|(y, line)| {
-                line.trim().char_indices().map(move |(x, ch)| match ch {
-                    '#' => (
-                        Coord {
-                            x: x as i32,
-                            y: y as i32,
-                            z: 0,
-                        },
-                        State::Active,
-                    ),
-                    '.' => (
-                        Coord {
-                            x: x as i32,
-                            y: y as i32,
-                            z: 0,
-                        },
-                        State::Inactive,
-                    ),
-                    _ => unreachable!(),
-                })
-            })
-            .flatten()
-            .collect()
-    }
+fn parse_counts(s: &str) -> Result<Vec<u32>, Error> {
+    let mut counts: Vec<u32> = if s.contains(',') {
+        s.split(',')
+            .map(|n| n.trim().parse())
+            .collect::<Result<_, _>>()?
+    } else {
+        s.chars()
+            .map(|ch| ch.to_digit(10).ok_or(format!("Invalid count {:?}", ch)))
+            .collect::<Result<_, _>>()?
+    };
+    counts.sort_unstable();
+    counts.dedup();
+    Ok(counts)
+}
 
-    fn apply_delta(coord: &Coord) -> Vec<Coord> {
-        (-1..=1)
-            .cartesian_product(-1..=1)
-            .cartesian_product(-1..=1)
-            .map(|((x, y), z)| (x, y, z))
-            .map(|(dx, dy, dz)| Coord {
-                x: coord.x + dx,
-                y: coord.y + dy,
-                z: coord.z + dz,
-            })
-            .collect()
+impl FromStr for LifeRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('/');
+        let (b, s) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(b), Some(s), None) => (b, s),
+            _ => return Err(format!("Rule {:?} must look like B3/S23", s).into()),
+        };
+        let birth = b
+            .strip_prefix('B')
+            .or_else(|| b.strip_prefix('b'))
+            .ok_or("Rule must start with B")?;
+        let survive = s
+            .strip_prefix('S')
+            .or_else(|| s.strip_prefix('s'))
+            .ok_or("Rule must continue with S")?;
+        let birth = parse_counts(birth)?;
+        if birth.contains(&0) {
+            // every empty cell in infinite space would be born
+            return Err("B0 rules are not supported".into());
+        }
+        Ok(Self {
+            birth,
+            survive: parse_counts(survive)?,
+        })
     }
+}
 
-    fn apply_deltas(coords: &[&Coord]) -> Vec<Coord> {
-        coords
-            .into_iter()
-            .map(|c| apply_delta(*c).into_iter())
-            .flatten()
-            .unique()
-            .collect()
+impl fmt::Display for LifeRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = |counts: &[u32]| {
+            let digits: Vec<_> = counts.iter().map(|c| c.to_string()).collect();
+            if counts.iter().all(|&c| c < 10) {
+                digits.concat()
+            } else {
+                digits.join(",")
+            }
+        };
+        write!(f, "B{}/S{}", counts(&self.birth), counts(&self.survive))
     }
 }
 
-mod p2 {
-    use super::*;
+type Coord<const N: usize> = [i32; N];
+
+/// Multiplicative hash over the coordinate words; the default SipHash
+/// dominates the running time in higher dimensions.
+#[derive(Debug, Default, Clone, Copy)]
+struct CoordHasher(u64);
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-    struct Coord {
-        x: i32,
-        y: i32,
-        z: i32,
-        w: i32,
+impl Hasher for CoordHasher {
+    fn finish(&self) -> u64 {
+        // the multiply leaves the low bits, which pick the bucket, weakest
+        self.0.rotate_left(26)
     }
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    enum State {
-        Active,
-        Inactive,
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
     }
 
-    pub fn run(input: &str) -> usize {
-        let init = read(input);
-        let last = simulate(init, 0, 6);
-        last.values().filter(|s| **s == State::Active).count()
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
     }
+}
+
+type CoordSet<const N: usize> = HashSet<Coord<N>, BuildHasherDefault<CoordHasher>>;
 
-    fn simulate(
-        old_state: HashMap<Coord, State>,
-        current_cycle: u32,
-        target_cycle: u32,
-    ) -> HashMap<Coord, State> {
-        if current_cycle == target_cycle {
-            old_state
+fn run<const N: usize>(
+    input: &str,
+    cycles: usize,
+    rule: &LifeRule,
+    symmetric: bool,
+    on_cycle: Option<Observer>,
+) -> Result<usize, Error> {
+    let mut on_cycle = on_cycle;
+    let mut sim = Simulation::<N>::new(input, rule.clone(), symmetric)?;
+    if let Some(observe) = &mut on_cycle {
+        observe(0, &sim.cells());
+    }
+    for cycle in 1..=cycles {
+        sim.step();
+        if let Some(observe) = &mut on_cycle {
+            observe(cycle, &sim.cells());
+        }
+    }
+    Ok(sim.population())
+}
+
+/// The seed is flat, so every dimension past `y` stays mirror-symmetric around
+/// 0. In symmetric mode only cells with those coordinates non-negative are
+/// stored, each standing in for all of its mirror images.
+#[derive(Debug, Clone)]
+struct Simulation<const N: usize> {
+    active: CoordSet<N>,
+    deltas: Vec<Coord<N>>,
+    rule: LifeRule,
+    symmetric: bool,
+}
+
+impl<const N: usize> Simulation<N> {
+    fn new(input: &str, rule: LifeRule, symmetric: bool) -> Result<Self, Error> {
+        Ok(Self {
+            active: read::<N>(input)?,
+            deltas: deltas::<N>(),
+            rule,
+            symmetric,
+        })
+    }
+
+    fn step(&mut self) {
+        self.active = if self.symmetric {
+            simulate_symmetric(&self.active, &self.deltas, &self.rule)
         } else {
-            let coords: Vec<_> = old_state.keys().collect();
-            let new_coords = apply_deltas(&coords);
-            let new_state = new_coords
-                .into_iter()
-                .map(|new_coord| {
-                    let active_count = apply_delta(&new_coord)
-                        .into_iter()
-                        .filter(|c| *c != new_coord)
-                        .filter(|c| match old_state.get(&c) {
-                            Some(State::Active) => true,
-                            _ => false,
+            simulate(&self.active, &self.deltas, &self.rule)
+        };
+    }
+
+    /// Number of active cells in the full space.
+    fn population(&self) -> usize {
+        if !self.symmetric {
+            return self.active.len();
+        }
+        self.active
+            .iter()
+            .map(|cell| 1 << cell[2..].iter().filter(|&&c| c != 0).count())
+            .sum()
+    }
+
+    /// Active cells of the full space, mirror images included, sorted.
+    fn cells(&self) -> Vec<Vec<i32>> {
+        let mut cells = vec![];
+        for cell in &self.active {
+            let mut images = vec![cell.to_vec()];
+            if self.symmetric {
+                for axis in (2..N).filter(|&axis| cell[axis] != 0) {
+                    let mirrors: Vec<_> = images
+                        .iter()
+                        .map(|image| {
+                            let mut image = image.clone();
+                            image[axis] = -image[axis];
+                            image
                         })
-                        .count();
-                    match old_state.get(&new_coord) {
-                        Some(State::Active) => match active_count {
-                            2 | 3 => (new_coord, State::Active),
-                            _ => (new_coord, State::Inactive),
-                        },
-                        _ => match active_count {
-                            3 => (new_coord, State::Active),
-                            _ => (new_coord, State::Inactive),
-                        },
-                    }
-                })
-                .collect();
-            simulate(new_state, current_cycle + 1, target_cycle)
+                        .collect();
+                    images.extend(mirrors);
+                }
+            }
+            cells.extend(images);
+        }
+        cells.sort_unstable();
+        cells
+    }
+}
+
+/// Active cells of the seed slice, placed at 0 in every extra dimension.
+fn read<const N: usize>(input: &str) -> Result<CoordSet<N>, Error> {
+    let mut active = CoordSet::default();
+    for (y, line) in input.lines().enumerate() {
+        for (x, ch) in line.trim().char_indices() {
+            match ch {
+                '#' => {
+                    let mut coord = [0; N];
+                    coord[0] = x as i32;
+                    coord[1] = y as i32;
+                    active.insert(coord);
+                }
+                '.' => (),
+                _ => return Err(format!("Invalid cube {:?} at {},{}", ch, x, y).into()),
+            }
         }
     }
+    Ok(active)
+}
 
-    fn read(input: &str) -> HashMap<Coord, State> {
-        input
-            .lines()
-            .enumerate()
-            .map(|(y, line)| {
-                line.trim().char_indices().map(move |(x, ch)| match ch {
-                    '#' => (
-                        Coord {
-                            x: x as i32,
-                            y: y as i32,
-                            z: 0,
-                            w: 0,
-                        },
-                        State::Active,
-                    ),
-                    '.' => (
-                        Coord {
-                            x: x as i32,
-                            y: y as i32,
-                            z: 0,
-                            w: 0,
-                        },
-                        State::Inactive,
-                    ),
-                    _ => unreachable!(),
+/// Every offset to a neighbor, i.e. all of `{-1, 0, 1}^N` but the origin.
+fn deltas<const N: usize>() -> Vec<Coord<N>> {
+    let mut deltas = vec![[0; N]];
+    for axis in 0..N {
+        deltas = deltas
+            .into_iter()
+            .flat_map(|d| {
+                (-1..=1).map(move |step| {
+                    let mut d = d;
+                    d[axis] = step;
+                    d
                 })
             })
-            .flatten()
-            .collect()
+            .collect();
     }
+    deltas.retain(|d| d.iter().any(|&v| v != 0));
+    deltas
+}
 
-    fn apply_delta(coord: &Coord) -> Vec<Coord> {
-        (-1..=1)
-            .cartesian_product(-1..=1)
-            .cartesian_product(-1..=1)
-            .cartesian_product(-1..=1)
-            .map(|(((x, y), z), w)| (x, y, z, w))
-            .map(|(dx, dy, dz, dw)| Coord {
-                x: coord.x + dx,
-                y: coord.y + dy,
-                z: coord.z + dz,
-                w: coord.w + dw,
-            })
-            .collect()
+/// One cycle. Each active cell adds itself to the neighbor count of the cells
+/// around it, so only active cells and cells next to them are ever looked at.
+fn simulate<const N: usize>(
+    active: &CoordSet<N>,
+    deltas: &[Coord<N>],
+    rule: &LifeRule,
+) -> CoordSet<N> {
+    let mut counts: HashMap<Coord<N>, u32, BuildHasherDefault<CoordHasher>> =
+        HashMap::with_capacity_and_hasher(active.len() * 4, Default::default());
+    // isolated cells still need a count of 0 to survive under S0
+    counts.extend(active.iter().map(|&cell| (cell, 0)));
+    for cell in active {
+        for delta in deltas {
+            let mut neighbor = *cell;
+            neighbor.iter_mut().zip(delta).for_each(|(c, d)| *c += d);
+            *counts.entry(neighbor).or_insert(0) += 1;
+        }
     }
+    counts
+        .into_iter()
+        .filter(|(cell, count)| rule.next(active.contains(cell), *count))
+        .map(|(cell, _)| cell)
+        .collect()
+}
 
-    fn apply_deltas(coords: &[&Coord]) -> Vec<Coord> {
-        coords
-            .into_iter()
-            .map(|c| apply_delta(*c).into_iter())
-            .flatten()
-            .unique()
-            .collect()
+/// One cycle over the non-negative orthant of the extra dimensions. Neighbors
+/// that fall outside are mirrors of cells counted through another delta; a
+/// cell at 1 on an extra axis also counts for its mirror at -1 when seen from 0.
+fn simulate_symmetric<const N: usize>(
+    active: &CoordSet<N>,
+    deltas: &[Coord<N>],
+    rule: &LifeRule,
+) -> CoordSet<N> {
+    let mut counts: HashMap<Coord<N>, u32, BuildHasherDefault<CoordHasher>> =
+        HashMap::with_capacity_and_hasher(active.len() * 4, Default::default());
+    // isolated cells still need a count of 0 to survive under S0
+    counts.extend(active.iter().map(|&cell| (cell, 0)));
+    for cell in active {
+        for delta in deltas {
+            let mut neighbor = *cell;
+            neighbor.iter_mut().zip(delta).for_each(|(c, d)| *c += d);
+            if neighbor[2..].iter().any(|&c| c < 0) {
+                continue;
+            }
+            let mirrored = (2..N)
+                .filter(|&axis| cell[axis] == 1 && neighbor[axis] == 0)
+                .count();
+            *counts.entry(neighbor).or_insert(0) += 1 << mirrored;
+        }
     }
+    counts
+        .into_iter()
+        .filter(|(cell, count)| rule.next(active.contains(cell), *count))
+        .map(|(cell, _)| cell)
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn population(
+        input: &str,
+        dims: usize,
+        cycles: usize,
+        rule: &LifeRule,
+        symmetric: bool,
+    ) -> Result<usize, Error> {
+        run_dims(input, dims, cycles, rule, symmetric, None)
+    }
+
     const SAMPLE: &str = r#".#.
     ..#
     ###"#;
 
     #[test]
     fn test_p1() {
-        assert_eq!(112, p1::run(SAMPLE))
+        assert_eq!(
+            112,
+            population(SAMPLE, 3, 6, &LifeRule::default(), false).unwrap()
+        )
     }
 
     #[test]
     fn test_p2() {
-        assert_eq!(848, p2::run(SAMPLE))
+        assert_eq!(
+            848,
+            population(SAMPLE, 4, 6, &LifeRule::default(), false).unwrap()
+        )
+    }
+
+    #[test]
+    fn test_dimensions() {
+        assert_eq!(26, deltas::<3>().len());
+        assert_eq!(728, deltas::<6>().len());
+        // the sample is a glider in plain 2D Life
+        let conway = LifeRule::default();
+        for cycles in 0..8 {
+            assert_eq!(5, population(SAMPLE, 2, cycles, &conway, false).unwrap());
+        }
+        assert_eq!(112, population(SAMPLE, 3, 6, &conway, false).unwrap());
+        assert!(population(SAMPLE, 5, 2, &conway, false).unwrap() > 0);
+        assert!(population(SAMPLE, 1, 6, &conway, false).is_err());
+        assert!(population("#?", 3, 1, &conway, false).is_err());
+    }
+
+    #[test]
+    fn test_rules() {
+        let conway: LifeRule = "B3/S23".parse().unwrap();
+        assert_eq!(LifeRule::default(), conway);
+        assert_eq!("B3/S23", conway.to_string());
+        let wide: LifeRule = "b3,12/s".parse().unwrap();
+        assert_eq!(vec![3, 12], wide.birth);
+        assert!(wide.survive.is_empty());
+        assert_eq!("B3,12/S", wide.to_string());
+        assert!("B3".parse::<LifeRule>().is_err());
+        assert!("S23/B3".parse::<LifeRule>().is_err());
+        assert!("B3x/S23".parse::<LifeRule>().is_err());
+        assert!("B03/S23".parse::<LifeRule>().is_err());
+
+        // B36/S23 is HighLife, in which the sample still glides in 2D
+        let highlife: LifeRule = "B36/S23".parse().unwrap();
+        assert_eq!(5, population(SAMPLE, 2, 8, &highlife, false).unwrap());
+
+        // a lone cell has no neighbors at all, which S0 lets it survive
+        let lonely: LifeRule = "B3/S0".parse().unwrap();
+        for dims in 2..=4 {
+            assert_eq!(1, population("#", dims, 3, &lonely, false).unwrap());
+            assert_eq!(1, population("#", dims, 3, &lonely, true).unwrap());
+        }
+        assert_eq!(0, population("#", 3, 1, &conway, false).unwrap());
+    }
+
+    #[test]
+    fn test_symmetric() {
+        let rules = ["B3/S23", "B36/S23", "B2/S", "B3,4,5/S2,3,4,10"];
+        for rule in rules.iter() {
+            let rule: LifeRule = rule.parse().unwrap();
+            for dims in 2..=5 {
+                for cycles in 0..=4 {
+                    assert_eq!(
+                        population(SAMPLE, dims, cycles, &rule, false).unwrap(),
+                        population(SAMPLE, dims, cycles, &rule, true).unwrap(),
+                        "{} in {}D after {} cycles",
+                        rule,
+                        dims,
+                        cycles
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let mut frames = vec![];
+        let mut on_cycle = |cycle: usize, cells: &[Vec<i32>]| {
+            frames.push((cycle, render_slices(cells), csv_rows(cycle, cells)))
+        };
+        let conway = LifeRule::default();
+        run_dims(SAMPLE, 3, 1, &conway, true, Some(&mut on_cycle)).unwrap();
+        assert_eq!(2, frames.len());
+        assert_eq!("z=0\n.#.\n..#\n###\n", frames[0].1);
+        assert_eq!("0,0,2,0\n0,1,0,0\n0,1,2,0\n0,2,1,0\n0,2,2,0\n", frames[0].2);
+        // the first cycle of the puzzle text, mirror images restored
+        let expected = "z=-1\n#..\n..#\n.#.\n\n\
+                        z=0\n#.#\n.##\n.#.\n\n\
+                        z=1\n#..\n..#\n.#.\n";
+        assert_eq!(expected, frames[1].1);
+        assert_eq!(11, frames[1].2.lines().count());
+        assert_eq!("cycle,x,y,z,w\n", csv_header(4));
+
+        let mut slices = vec![];
+        run_dims(
+            SAMPLE,
+            4,
+            1,
+            &conway,
+            false,
+            Some(&mut |_, cells| slices.push(render_slices(cells))),
+        )
+        .unwrap();
+        assert!(slices[1].starts_with("z=-1, w=-1\n#..\n..#\n.#.\n\nz=0, w=-1\n"));
+        assert_eq!(9, slices[1].matches("w=").count());
+        assert_eq!("(empty)\n", render_slices(&[]));
     }
 }
@@ -1,169 +1,813 @@
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fmt,
+    io::{BufRead, Write},
+    str::FromStr,
+};
+
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+
 type Error = Box<dyn std::error::Error>;
 
-fn main() {
+fn main() -> Result<(), Error> {
+    let mut table = None;
+    let mut int = None;
+    let mut repl = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--table" => table = Some(OperatorTable::from_str(&value()?)?),
+            "--int" => int = Some(IntType::from_str(&value()?)?),
+            "--repl" => repl = true,
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+
+    if repl {
+        let mut repl = Repl::new(int.unwrap_or(IntType::I64));
+        if let Some(table) = table {
+            repl.table = table;
+            repl.mode = "custom".to_string();
+        }
+        return repl.run();
+    }
+
     let input = include_str!("..\\input.txt");
-    assert_eq!(24650385570008, p1(input));
-    assert_eq!(158183007916215, p2(input));
-    println!("All done")
+    if table.is_some() || int.is_some() {
+        let table = table.unwrap_or_else(OperatorTable::addition_first);
+        println!("{}", int.unwrap_or(IntType::I64).sum(input, &table)?);
+        return Ok(());
+    }
+    assert_eq!(24650385570008, p1(input)?);
+    assert_eq!(158183007916215, p2(input)?);
+    println!("All done");
+    Ok(())
+}
+
+fn p1(input: &str) -> Result<i64, Error> {
+    sum(input, &OperatorTable::same_precedence())
+}
+
+fn p2(input: &str) -> Result<i64, Error> {
+    sum(input, &OperatorTable::addition_first())
 }
 
-fn p1(input: &str) -> i64 {
-    input
-        .lines()
-        .map(parse_line)
-        .filter_map(Result::ok)
-        .map(|tokens| evaluate(&tokens, 0, true, false).0)
-        .sum()
+/// Sums every non-empty line, naming the line of the first one that fails.
+fn sum<T: Number>(input: &str, table: &OperatorTable) -> Result<T, Error> {
+    let mut total = T::zero();
+    for (idx, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = parse(line, table)
+            .and_then(|expr| expr.eval::<T>())
+            .map_err(|e| format!("line {}: {}", idx + 1, e))?;
+        total = BinaryOp::Add
+            .apply(&total, &value)
+            .map_err(|e| format!("line {}: sum {}", idx + 1, e))?;
+    }
+    Ok(total)
+}
+
+/// Integer type the homework is evaluated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IntType {
+    I64,
+    I128,
+    Big,
+}
+
+impl IntType {
+    fn sum(self, input: &str, table: &OperatorTable) -> Result<String, Error> {
+        Ok(match self {
+            Self::I64 => sum::<i64>(input, table)?.to_string(),
+            Self::I128 => sum::<i128>(input, table)?.to_string(),
+            Self::Big => sum::<BigInt>(input, table)?.to_string(),
+        })
+    }
+
+    fn eval(self, expr: &Expr) -> Result<String, Error> {
+        Ok(match self {
+            Self::I64 => expr.eval::<i64>()?.to_string(),
+            Self::I128 => expr.eval::<i128>()?.to_string(),
+            Self::Big => expr.eval::<BigInt>()?.to_string(),
+        })
+    }
+}
+
+impl FromStr for IntType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "i64" => Ok(Self::I64),
+            "i128" => Ok(Self::I128),
+            "big" => Ok(Self::Big),
+            _ => Err(format!("Integer type {:?} must be i64, i128 or big", s).into()),
+        }
+    }
+}
+
+/// Checked arithmetic; `None` means the result does not fit.
+trait Number: Sized + PartialOrd + fmt::Display {
+    fn zero() -> Self;
+    fn from_literal(num: u128) -> Option<Self>;
+    fn to_exponent(&self) -> Option<u32>;
+    fn add(&self, rhs: &Self) -> Option<Self>;
+    fn sub(&self, rhs: &Self) -> Option<Self>;
+    fn mul(&self, rhs: &Self) -> Option<Self>;
+    /// Truncating, `rhs` is never zero.
+    fn div(&self, rhs: &Self) -> Option<Self>;
+    fn rem(&self, rhs: &Self) -> Option<Self>;
+    fn pow(&self, exp: u32) -> Option<Self>;
+    fn neg(&self) -> Option<Self>;
+}
+
+macro_rules! impl_number {
+    ($($t:ty),*) => {$(
+        impl Number for $t {
+            fn zero() -> Self {
+                0
+            }
+
+            fn from_literal(num: u128) -> Option<Self> {
+                num.try_into().ok()
+            }
+
+            fn to_exponent(&self) -> Option<u32> {
+                (*self).try_into().ok()
+            }
+
+            fn add(&self, rhs: &Self) -> Option<Self> {
+                self.checked_add(*rhs)
+            }
+
+            fn sub(&self, rhs: &Self) -> Option<Self> {
+                self.checked_sub(*rhs)
+            }
+
+            fn mul(&self, rhs: &Self) -> Option<Self> {
+                self.checked_mul(*rhs)
+            }
+
+            fn div(&self, rhs: &Self) -> Option<Self> {
+                self.checked_div(*rhs)
+            }
+
+            fn rem(&self, rhs: &Self) -> Option<Self> {
+                self.checked_rem(*rhs)
+            }
+
+            fn pow(&self, exp: u32) -> Option<Self> {
+                self.checked_pow(exp)
+            }
+
+            fn neg(&self) -> Option<Self> {
+                self.checked_neg()
+            }
+        }
+    )*};
 }
 
-fn p2(input: &str) -> i64 {
-    input
-        .lines()
-        .map(parse_line)
-        .filter_map(Result::ok)
-        .map(|tokens| evaluate(&tokens, 0, false, false).0)
-        .sum()
+impl_number!(i64, i128);
+
+impl Number for BigInt {
+    fn zero() -> Self {
+        Zero::zero()
+    }
+
+    fn from_literal(num: u128) -> Option<Self> {
+        Some(num.into())
+    }
+
+    fn to_exponent(&self) -> Option<u32> {
+        self.to_u32()
+    }
+
+    fn add(&self, rhs: &Self) -> Option<Self> {
+        Some(self + rhs)
+    }
+
+    fn sub(&self, rhs: &Self) -> Option<Self> {
+        Some(self - rhs)
+    }
+
+    fn mul(&self, rhs: &Self) -> Option<Self> {
+        Some(self * rhs)
+    }
+
+    fn div(&self, rhs: &Self) -> Option<Self> {
+        Some(self / rhs)
+    }
+
+    fn rem(&self, rhs: &Self) -> Option<Self> {
+        Some(self % rhs)
+    }
+
+    fn pow(&self, exp: u32) -> Option<Self> {
+        Some(num_traits::pow(self.clone(), exp as usize))
+    }
+
+    fn neg(&self) -> Option<Self> {
+        Some(-self)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Token {
-    Num(i64),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Num(u128),
     Op(BinaryOp),
     LeftParen,
     RightParen,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Num(num) => write!(f, "{}", num),
+            Self::Op(op) => write!(f, "'{}'", op.symbol()),
+            Self::LeftParen => write!(f, "'('"),
+            Self::RightParen => write!(f, "')'"),
+        }
+    }
+}
+
+/// A token and the 1-based column it starts at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum BinaryOp {
     Add,
+    Sub,
     Mul,
+    Div,
+    Mod,
+    Pow,
 }
 
-fn evaluate(tokens: &[Token], mut idx: usize, no_precedence: bool, deepdown: bool) -> (i64, usize) {
-    let mut res = 0;
-    let mut op = BinaryOp::Add;
-    while idx < tokens.len() {
-        match tokens[idx] {
-            Token::Num(num) => match op {
-                BinaryOp::Add => res += num,
-                BinaryOp::Mul => res *= num,
-            },
-            Token::Op(bin_op) => match bin_op {
-                BinaryOp::Add => op = BinaryOp::Add,
-                BinaryOp::Mul => {
-                    if no_precedence {
-                        op = BinaryOp::Mul;
-                    } else if deepdown {
-                        return (res, idx - 1);
-                    } else {
-                        let (sub_result, i) = evaluate(tokens, idx + 1, no_precedence, true);
-                        res *= sub_result;
-                        idx = i
-                    }
-                }
-            },
-            Token::LeftParen => {
-                let (sub_result, i) = evaluate(tokens, idx + 1, no_precedence, false);
-                match op {
-                    BinaryOp::Add => res += sub_result,
-                    BinaryOp::Mul => res *= sub_result,
-                }
-                idx = i
+impl BinaryOp {
+    fn symbol(self) -> char {
+        match self {
+            Self::Add => '+',
+            Self::Sub => '-',
+            Self::Mul => '*',
+            Self::Div => '/',
+            Self::Mod => '%',
+            Self::Pow => '^',
+        }
+    }
+
+    fn from_symbol(ch: char) -> Option<Self> {
+        match ch {
+            '+' => Some(Self::Add),
+            '-' => Some(Self::Sub),
+            '*' => Some(Self::Mul),
+            '/' => Some(Self::Div),
+            '%' => Some(Self::Mod),
+            '^' => Some(Self::Pow),
+            _ => None,
+        }
+    }
+
+    fn apply<T: Number>(self, lhs: &T, rhs: &T) -> Result<T, Error> {
+        let describe = || format!("{} {} {}", lhs, self.symbol(), rhs);
+        let result = match self {
+            Self::Add => lhs.add(rhs),
+            Self::Sub => lhs.sub(rhs),
+            Self::Mul => lhs.mul(rhs),
+            Self::Div | Self::Mod if *rhs == T::zero() => {
+                return Err(format!("division by zero in {}", describe()).into())
             }
-            Token::RightParen => {
-                if deepdown {
-                    return (res, idx - 1);
-                } else {
-                    return (res, idx);
-                }
+            Self::Div => lhs.div(rhs),
+            Self::Mod => lhs.rem(rhs),
+            Self::Pow if *rhs < T::zero() => {
+                return Err(format!("negative exponent in {}", describe()).into())
             }
-        }
-        idx += 1
+            Self::Pow => rhs.to_exponent().and_then(|exp| lhs.pow(exp)),
+        };
+        result.ok_or_else(|| format!("overflow in {}", describe()).into())
     }
-    (res, idx)
 }
 
-fn parse_line(line: &str) -> Result<Vec<Token>, Error> {
-    let mut chars = line.chars().filter(|c| !c.is_ascii_whitespace()).peekable();
+fn tokenize(line: &str) -> Result<Vec<Token>, Error> {
+    let mut chars = line.char_indices().peekable();
     let mut tokens = vec![];
-    while let Some(c) = chars.next() {
-        match c {
-            '+' => tokens.push(Token::Op(BinaryOp::Add)),
-            '*' => tokens.push(Token::Op(BinaryOp::Mul)),
-            '(' => tokens.push(Token::LeftParen),
-            ')' => tokens.push(Token::RightParen),
+    while let Some((idx, c)) = chars.next() {
+        let column = idx + 1;
+        let kind = match c {
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
             '0'..='9' => {
-                let mut num = String::new();
-                num.push(c);
-                while let Some(next_char) = chars.peek() {
-                    if next_char.is_ascii_digit() {
-                        num.push(*next_char);
-                        chars.next();
-                    } else {
+                let mut end = idx + 1;
+                while let Some(&(i, next_char)) = chars.peek() {
+                    if !next_char.is_ascii_digit() {
                         break;
                     }
+                    end = i + 1;
+                    chars.next();
                 }
-                tokens.push(Token::Num(num.parse()?))
+                let num = line[idx..end]
+                    .parse()
+                    .map_err(|_| format!("column {}: number too large", column))?;
+                TokenKind::Num(num)
             }
-            _ => unreachable!(),
-        }
+            c if c.is_ascii_whitespace() => continue,
+            c => match BinaryOp::from_symbol(c) {
+                Some(op) => TokenKind::Op(op),
+                None => return Err(format!("column {}: unexpected {:?}", column, c).into()),
+            },
+        };
+        tokens.push(Token { kind, column });
     }
     Ok(tokens)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Num(u128),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
 
-    #[test]
-    fn test_p1() {
-        {
-            let tokens = parse_line("1 + 2 * 3 + 4 * 5 + 6").unwrap();
-            assert_eq!(71, evaluate(&tokens, 0, true, false).0)
+impl Expr {
+    fn eval<T: Number>(&self) -> Result<T, Error> {
+        match self {
+            Self::Num(num) => {
+                T::from_literal(*num).ok_or_else(|| format!("{} does not fit", num).into())
+            }
+            Self::Neg(expr) => {
+                let value = expr.eval::<T>()?;
+                value
+                    .neg()
+                    .ok_or_else(|| format!("overflow in -{}", value).into())
+            }
+            Self::Binary(op, lhs, rhs) => op.apply(&lhs.eval()?, &rhs.eval()?),
         }
-        {
-            let tokens = parse_line("2 * 3 + (4 * 5)").unwrap();
-            assert_eq!(26, evaluate(&tokens, 0, true, false).0)
+    }
+}
+
+/// Fully parenthesized, so the grouping the parser chose is visible.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Num(num) => write!(f, "{}", num),
+            Self::Neg(expr) => write!(f, "(-{})", expr),
+            Self::Binary(op, lhs, rhs) => write!(f, "({} {} {})", lhs, op.symbol(), rhs),
         }
-        {
-            let tokens = parse_line("5 + (8 * 3 + 9 + 3 * 4 * 3)").unwrap();
-            assert_eq!(437, evaluate(&tokens, 0, true, false).0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assoc {
+    Left,
+    Right,
+}
+
+/// Binding power and associativity of each operator. Operators left out of
+/// the table are rejected by the parser. Unary minus takes an operand made of
+/// operators binding at least as tight as `negation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct OperatorTable {
+    ops: HashMap<BinaryOp, (u8, Assoc)>,
+    negation: u8,
+}
+
+impl OperatorTable {
+    /// Part one: evaluated strictly left to right.
+    fn same_precedence() -> Self {
+        use BinaryOp::*;
+        Self {
+            ops: [Add, Sub, Mul, Div, Mod, Pow]
+                .iter()
+                .map(|&op| (op, (1, Assoc::Left)))
+                .collect(),
+            negation: 2,
         }
-        {
-            let tokens = parse_line("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))").unwrap();
-            assert_eq!(12240, evaluate(&tokens, 0, true, false).0)
+    }
+
+    /// Part two: `+` and `-` bind tighter than `*`, `/` and `%`.
+    fn addition_first() -> Self {
+        use BinaryOp::*;
+        Self {
+            ops: vec![
+                (Add, (2, Assoc::Left)),
+                (Sub, (2, Assoc::Left)),
+                (Mul, (1, Assoc::Left)),
+                (Div, (1, Assoc::Left)),
+                (Mod, (1, Assoc::Left)),
+                (Pow, (3, Assoc::Right)),
+            ]
+            .into_iter()
+            .collect(),
+            negation: 3,
         }
-        {
-            let tokens = parse_line("((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2").unwrap();
-            assert_eq!(13632, evaluate(&tokens, 0, true, false).0)
+    }
+}
+
+/// Preset tables by the name the REPL knows them under.
+fn preset(name: &str) -> Option<OperatorTable> {
+    match name {
+        "same" => Some(OperatorTable::same_precedence()),
+        "addition" => Some(OperatorTable::addition_first()),
+        _ => None,
+    }
+}
+
+/// Comma separated `op:precedence[:left|:right]` entries, e.g. `+:2,*:1`, and
+/// `neg:precedence` for unary minus, which otherwise binds tightest.
+impl FromStr for OperatorTable {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ops = HashMap::new();
+        let mut negation = None;
+        for entry in s.split(',') {
+            if let Some(precedence) = entry.trim().strip_prefix("neg:") {
+                negation = Some(precedence.parse()?);
+                continue;
+            }
+            let mut parts = entry.trim().split(':');
+            let mut symbol = parts.next().unwrap_or("").chars();
+            let op = match (symbol.next(), symbol.next()) {
+                (Some(ch), None) => BinaryOp::from_symbol(ch),
+                _ => None,
+            }
+            .ok_or_else(|| format!("Unknown operator in {:?}", entry))?;
+            let precedence: u8 = parts
+                .next()
+                .ok_or_else(|| format!("Missing precedence in {:?}", entry))?
+                .parse()?;
+            let assoc = match parts.next() {
+                None | Some("left") => Assoc::Left,
+                Some("right") => Assoc::Right,
+                Some(s) => return Err(format!("Unknown associativity {:?}", s).into()),
+            };
+            if ops.insert(op, (precedence, assoc)).is_some() {
+                return Err(format!("Operator {} listed twice", op.symbol()).into());
+            }
         }
+        let tightest = ops.values().map(|&(precedence, _)| precedence).max();
+        let negation = negation.unwrap_or_else(|| tightest.map_or(0, |p| p.saturating_add(1)));
+        Ok(Self { ops, negation })
     }
+}
 
-    #[test]
-    fn test_p2() {
-        {
-            let tokens = parse_line("1 + 2 * 3 + 4 * 5 + 6").unwrap();
-            assert_eq!(231, evaluate(&tokens, 0, false, false).0)
+fn parse(line: &str, table: &OperatorTable) -> Result<Expr, Error> {
+    let tokens = tokenize(line)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        table,
+        end: line.len() + 1,
+    };
+    let expr = parser.expr(0)?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(Token {
+            kind: TokenKind::RightParen,
+            column,
+        }) => Err(format!("column {}: unmatched ')'", column).into()),
+        Some(Token { column, .. }) => {
+            Err(format!("column {}: expected an operator", column).into())
         }
+    }
+}
+
+/// Pratt parser: each operator with enough binding power takes the expression
+/// so far as its left operand.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    table: &'a OperatorTable,
+    /// Column reported for errors at the end of the line.
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn expr(&mut self, min_precedence: u16) -> Result<Expr, Error> {
+        let mut lhs = self.primary()?;
+        while let Some(Token {
+            kind: TokenKind::Op(op),
+            column,
+        }) = self.peek()
         {
-            let tokens = parse_line("1 + (2 * 3) + (4 * (5 + 6))").unwrap();
-            assert_eq!(51, evaluate(&tokens, 0, false, false).0)
+            let (precedence, assoc) = *self.table.ops.get(&op).ok_or_else(|| {
+                format!(
+                    "column {}: operator {} is not in the table",
+                    column,
+                    op.symbol()
+                )
+            })?;
+            // widened so that `precedence + 1` cannot overflow
+            let precedence = u16::from(precedence);
+            if precedence < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let next = match assoc {
+                Assoc::Left => precedence + 1,
+                Assoc::Right => precedence,
+            };
+            let rhs = self.expr(next)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
         }
-        {
-            let tokens = parse_line("2 * 3 + (4 * 5)").unwrap();
-            assert_eq!(46, evaluate(&tokens, 0, false, false).0)
+        Ok(lhs)
+    }
+
+    fn primary(&mut self) -> Result<Expr, Error> {
+        let token = self
+            .peek()
+            .ok_or_else(|| format!("column {}: unexpected end of line", self.end))?;
+        self.pos += 1;
+        match token.kind {
+            TokenKind::Num(num) => Ok(Expr::Num(num)),
+            TokenKind::Op(BinaryOp::Sub) => {
+                let operand = self.expr(u16::from(self.table.negation))?;
+                Ok(Expr::Neg(Box::new(operand)))
+            }
+            TokenKind::LeftParen => {
+                let inner = self.expr(0)?;
+                match self.peek() {
+                    Some(Token {
+                        kind: TokenKind::RightParen,
+                        ..
+                    }) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    Some(found) => Err(format!(
+                        "column {}: expected ')' but found {}",
+                        found.column, found.kind
+                    )
+                    .into()),
+                    None => Err(format!("column {}: unclosed '('", token.column).into()),
+                }
+            }
+            TokenKind::RightParen => Err(format!("column {}: unmatched ')'", token.column).into()),
+            TokenKind::Op(op) => Err(format!(
+                "column {}: expected a number before {}",
+                token.column,
+                op.symbol()
+            )
+            .into()),
         }
-        {
-            let tokens = parse_line("5 + (8 * 3 + 9 + 3 * 4 * 3)").unwrap();
-            assert_eq!(1445, evaluate(&tokens, 0, false, false).0)
+    }
+}
+
+const REPL_HELP: &str = "\
+expressions are evaluated under the current mode
+:mode             show the current mode
+:mode same        every operator binds equally, left to right
+:mode addition    + and - bind tighter than *, / and %
+:mode TABLE       custom table such as +:2,*:1 or *:2,+:1,^:3:right
+:tree EXPR        show how EXPR is grouped
+:quit             leave";
+
+/// Line-based calculator for trying out precedence schemes.
+struct Repl {
+    table: OperatorTable,
+    mode: String,
+    int: IntType,
+}
+
+impl Repl {
+    fn new(int: IntType) -> Self {
+        Self {
+            table: OperatorTable::addition_first(),
+            mode: "addition".to_string(),
+            int,
         }
-        {
-            let tokens = parse_line("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))").unwrap();
-            assert_eq!(669060, evaluate(&tokens, 0, false, false).0)
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
+        let stdin = std::io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("{}> ", self.mode);
+            std::io::stdout().flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            let line = line.trim();
+            if line == ":quit" || line == ":q" {
+                return Ok(());
+            }
+            match self.handle(line) {
+                Ok(out) if out.is_empty() => (),
+                Ok(out) => println!("{}", out),
+                Err(e) => println!("error: {}", e),
+            }
         }
-        {
-            let tokens = parse_line("((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2").unwrap();
-            assert_eq!(23340, evaluate(&tokens, 0, false, false).0)
+    }
+
+    /// Runs one command or expression and returns what to print.
+    fn handle(&mut self, line: &str) -> Result<String, Error> {
+        let (command, rest) = match line.find(char::is_whitespace) {
+            Some(idx) => (&line[..idx], line[idx..].trim()),
+            None => (line, ""),
+        };
+        match command {
+            "" => Ok(String::new()),
+            ":help" => Ok(REPL_HELP.to_string()),
+            ":mode" if rest.is_empty() => Ok(format!("mode {}", self.mode)),
+            ":mode" => {
+                let (mode, table) = match preset(rest) {
+                    Some(table) => (rest.to_string(), table),
+                    None => ("custom".to_string(), rest.parse()?),
+                };
+                self.mode = mode;
+                self.table = table;
+                Ok(format!("mode {}", self.mode))
+            }
+            ":tree" => Ok(parse(rest, &self.table)?.to_string()),
+            c if c.starts_with(':') => Err(format!("unknown command {}, try :help", c).into()),
+            _ => self.int.eval(&parse(line, &self.table)?),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(line: &str, table: &OperatorTable) -> i64 {
+        parse(line, table).unwrap().eval().unwrap()
+    }
+
+    #[test]
+    fn test_p1() {
+        let table = OperatorTable::same_precedence();
+        assert_eq!(71, eval("1 + 2 * 3 + 4 * 5 + 6", &table));
+        assert_eq!(26, eval("2 * 3 + (4 * 5)", &table));
+        assert_eq!(437, eval("5 + (8 * 3 + 9 + 3 * 4 * 3)", &table));
+        assert_eq!(
+            12240,
+            eval("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))", &table)
+        );
+        assert_eq!(
+            13632,
+            eval("((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2", &table)
+        );
+    }
+
+    #[test]
+    fn test_p2() {
+        let table = OperatorTable::addition_first();
+        assert_eq!(231, eval("1 + 2 * 3 + 4 * 5 + 6", &table));
+        assert_eq!(51, eval("1 + (2 * 3) + (4 * (5 + 6))", &table));
+        assert_eq!(46, eval("2 * 3 + (4 * 5)", &table));
+        assert_eq!(1445, eval("5 + (8 * 3 + 9 + 3 * 4 * 3)", &table));
+        assert_eq!(
+            669060,
+            eval("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))", &table)
+        );
+        assert_eq!(
+            23340,
+            eval("((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2", &table)
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        // the usual precedence, and the two puzzle schemes written out
+        let table: OperatorTable = "*:2,+:1".parse().unwrap();
+        assert_eq!(1 + 2 * 3 + 4 * 5 + 6, eval("1 + 2 * 3 + 4 * 5 + 6", &table));
+        assert_eq!(
+            OperatorTable::addition_first(),
+            "+:2,-:2,*:1:left,/:1,%:1,^:3:right,neg:3".parse().unwrap()
+        );
+        assert_eq!(
+            OperatorTable::same_precedence(),
+            "+:1, -:1, *:1, /:1, %:1, ^:1, neg:2".parse().unwrap()
+        );
+
+        let right: OperatorTable = "+:1:right,*:1:right".parse().unwrap();
+        assert_eq!(
+            parse("2 * (3 + 4)", &right).unwrap(),
+            parse("2 * 3 + 4", &right).unwrap()
+        );
+        assert!("&:1".parse::<OperatorTable>().is_err());
+        assert!("+:1,+:2".parse::<OperatorTable>().is_err());
+        assert!("+:1:up".parse::<OperatorTable>().is_err());
+
+        let err = parse("1 * 2", &"+:1".parse().unwrap()).unwrap_err();
+        assert_eq!("column 3: operator * is not in the table", err.to_string());
+    }
+
+    #[test]
+    fn test_errors() {
+        let table = OperatorTable::addition_first();
+        let err = |line| parse(line, &table).unwrap_err().to_string();
+        assert_eq!("column 5: unclosed '('", err("1 + (2 * (3 + 4)"));
+        assert_eq!("column 4: expected ')' but found 3", err("(2 3)"));
+        assert_eq!("column 8: expected ')' but found '('", err("(1 + 2 (3))"));
+        assert_eq!("column 8: unmatched ')'", err("(1 + 2))"));
+        assert_eq!("column 1: unmatched ')'", err(") + 1"));
+        assert_eq!("column 5: unexpected end of line", err("1 + "));
+        assert_eq!("column 3: expected an operator", err("1 2"));
+        assert_eq!("column 5: expected a number before *", err("1 + * 2"));
+        assert_eq!("column 3: unexpected '&'", err("1 & 2"));
+        assert_eq!("column 1: number too large", err(&"9".repeat(40)));
+        assert!(sum::<i64>("1 + 2\n\n(3", &table)
+            .unwrap_err()
+            .to_string()
+            .starts_with("line 3: "));
+    }
+
+    #[test]
+    fn test_operators() {
+        let table = OperatorTable::addition_first();
+        assert_eq!(-3, eval("1 - 2 * 3", &table));
+        // 7 / (2 - 5) % 2 * 3
+        assert_eq!(0, eval("7 / 2 - 5 % 2 * 3", &table));
+        assert_eq!(512, eval("2 ^ 3 ^ 2", &table));
+        assert_eq!(-4, eval("-2 ^ 2", &table));
+        assert_eq!(4, eval("(-2) ^ 2", &table));
+        assert_eq!(5, eval("--5", &table));
+        assert_eq!(-3, eval("-7 / 2", &table));
+        assert_eq!(12, eval("1 - -2 * 4", &table));
+
+        let table = OperatorTable::same_precedence();
+        assert_eq!(-1, eval("1 - 2 * 3 + 2", &table));
+        assert_eq!(4, eval("-2 ^ 2", &table));
+    }
+
+    #[test]
+    fn test_checked() {
+        let table = OperatorTable::addition_first();
+        let err = |line: &str| {
+            parse(line, &table)
+                .unwrap()
+                .eval::<i64>()
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!("division by zero in 1 / 0", err("1 / (2 - 2)"));
+        assert_eq!("division by zero in 1 % 0", err("1 % 0"));
+        assert_eq!("negative exponent in 2 ^ -1", err("2 ^ -1"));
+        assert_eq!(
+            "overflow in 9223372036854775807 + 1",
+            err("9223372036854775807 + 1")
+        );
+        assert_eq!("overflow in 2 ^ 4294967296", err("2 ^ 4294967296"));
+        assert_eq!(
+            "9223372036854775808 does not fit",
+            err("9223372036854775808")
+        );
+        assert_eq!(i64::MIN, eval("-9223372036854775807 - 1", &table));
+
+        let big = "2 ^ 100\n3 * 2 ^ 70";
+        assert!(sum::<i64>(big, &table)
+            .unwrap_err()
+            .to_string()
+            .starts_with("line 1: overflow"));
+        let expected = (1i128 << 100) + 3 * (1i128 << 70);
+        assert_eq!(expected, sum::<i128>(big, &table).unwrap());
+        assert_eq!(expected.to_string(), IntType::Big.sum(big, &table).unwrap());
+        assert_eq!(
+            "1267650600228229401496703205376",
+            IntType::Big.sum("2 ^ 50 ^ 2 / 2 ^ 2400", &table).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut repl = Repl::new(IntType::I64);
+        let mut run = |line| match repl.handle(line) {
+            Ok(out) => out,
+            Err(e) => format!("error: {}", e),
+        };
+        assert_eq!("231", run("1 + 2 * 3 + 4 * 5 + 6"));
+        assert_eq!("((1 + 2) * (3 + 4))", run(":tree 1 + 2 * 3 + 4"));
+        assert_eq!("mode same", run(":mode same"));
+        assert_eq!("mode same", run(":mode"));
+        assert_eq!("71", run("1 + 2 * 3 + 4 * 5 + 6"));
+        assert_eq!("(((1 + 2) * 3) + 4)", run(":tree 1 + 2 * 3 + 4"));
+        assert_eq!("((-2) ^ 2)", run(":tree -2 ^ 2"));
+        assert_eq!("mode custom", run(":mode *:2,+:1,^:3:right"));
+        assert_eq!("(1 + (2 ^ (3 ^ 2)))", run(":tree 1 + 2 ^ 3 ^ 2"));
+        assert_eq!("mode addition", run(":mode addition"));
+        assert_eq!("", run("   "));
+        assert_eq!("error: division by zero in 1 / 0", run("1 / 0"));
+        assert_eq!("error: column 1: unclosed '('", run("(1"));
+        assert_eq!("error: Unknown operator in \"x:1\"", run(":mode x:1"));
+        assert_eq!("mode addition", run(":mode"));
+        assert!(run(":frobnicate").starts_with("error: unknown command"));
+        assert!(run(":help").contains(":tree"));
+    }
+}
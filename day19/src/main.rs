@@ -1,152 +1,721 @@
-use std::collections::HashMap;
-
-use itertools::Itertools;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
 
 type Error = Box<dyn std::error::Error>;
 
-fn main() {
+fn main() -> Result<(), Error> {
+    let mut regex = false;
+    let mut explain = false;
+    let mut loops = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--regex" => regex = true,
+            "--explain" => explain = true,
+            "--loops" => loops = true,
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+
     let input = include_str!("..\\input.txt");
     let (r, messages) = read(input);
-    let rule_map = parse_rules(&r).unwrap();
-    let rules = build_rule(&rule_map, 0);
-    assert_eq!(222, count_valid(&rules, &messages));
-    assert_eq!(339, count_valid_loop(&rule_map, &messages));
-    println!("All done")
+    let mut rule_map = parse_rules(&r)?;
+    if loops {
+        rule_map = with_loops(&rule_map)?;
+    }
+    let mut fatal = false;
+    for problem in check_grammar(&rule_map, 0) {
+        eprintln!("{}", problem);
+        fatal |= problem.is_fatal();
+    }
+    if fatal {
+        return Err("Grammar cannot be matched".into());
+    }
+    if explain {
+        for message in messages {
+            println!("{}", message);
+            match derive(&rule_map, 0, message)? {
+                Ok(tree) => print!("{}", tree),
+                Err(mismatch) => println!("  {}", mismatch),
+            }
+        }
+        return Ok(());
+    }
+    if regex {
+        println!("{}", to_regex(&rule_map, 0)?);
+        return Ok(());
+    }
+    if loops {
+        println!("{}", count_matching(&rule_map, &messages)?);
+        return Ok(());
+    }
+    let dfa = Dfa::compile(&rule_map, 0)?;
+    assert_eq!(222, dfa.count_accepted(&messages));
+    assert_eq!(222, count_matching(&rule_map, &messages)?);
+    let loop_map = with_loops(&rule_map)?;
+    assert_eq!(339, count_matching(&loop_map, &messages)?);
+    println!("All done");
+    Ok(())
+}
+
+/// Part two swaps in two self-referencing rules.
+const LOOP_RULES: [&str; 2] = ["8: 42 | 42 8", "11: 42 31 | 42 11 31"];
+
+fn with_loops(rules: &HashMap<u32, Rule>) -> Result<HashMap<u32, Rule>, Error> {
+    let mut rules = rules.clone();
+    for line in LOOP_RULES.iter() {
+        let (id, rule) = parse_rule(line)?;
+        rules.insert(id, rule);
+    }
+    Ok(rules)
 }
 
-fn count_valid_loop(rules: &HashMap<u32, Rule>, messages: &[&str]) -> usize {
-    messages
-        .into_iter()
+fn count_matching(rules: &HashMap<u32, Rule>, messages: &[&str]) -> Result<usize, Error> {
+    // the grammar is checked once and the matcher reused for every message
+    let mut matcher = Matcher::new(rules, 0, "")?;
+    Ok(messages
+        .iter()
         .filter(|message| {
-            let mut count_42 = 0;
-            let mut remainder = **message;
-            let mut result = check_valid(rules, &rules[&42], remainder);
-
-            while let Ok(new_remainder) = result {
-                count_42 += 1;
-                remainder = new_remainder;
-                result = check_valid(rules, &rules[&42], remainder);
-            }
+            matcher.reset(message);
+            matcher.matches_all(0)
+        })
+        .count())
+}
 
-            if count_42 < 2 {
-                return false;
-            }
+/// Matches rules against one message, remembering how far it got.
+struct Matcher<'a> {
+    rules: &'a HashMap<u32, Rule>,
+    message: &'a str,
+    /// Furthest position any character rule was tried at.
+    furthest: usize,
+    /// Character rules that failed at `furthest`, with what they wanted.
+    failed: BTreeSet<(u32, char)>,
+}
+
+impl<'a> Matcher<'a> {
+    /// Fails if the grammar reachable from `start` has an undefined rule or
+    /// left recursion, either of which would stop matching from terminating.
+    fn new(rules: &'a HashMap<u32, Rule>, start: u32, message: &'a str) -> Result<Self, Error> {
+        if let Some(problem) = check_grammar(rules, start)
+            .into_iter()
+            .find(Problem::is_fatal)
+        {
+            return Err(problem.to_string().into());
+        }
+        Ok(Self {
+            rules,
+            message,
+            furthest: 0,
+            failed: BTreeSet::new(),
+        })
+    }
 
-            let mut count_31 = 0;
-            result = check_valid(rules, &rules[&31], remainder);
+    /// Starts over on another message with the same rules.
+    fn reset(&mut self, message: &'a str) {
+        self.message = message;
+        self.furthest = 0;
+        self.failed.clear();
+    }
 
-            while let Ok(new_remainder) = result {
-                count_31 += 1;
-                remainder = new_remainder;
-                result = check_valid(rules, &rules[&31], remainder);
+    fn matches_all(&mut self, rule_id: u32) -> bool {
+        self.ends(rule_id, 0).contains(&self.message.len())
+    }
+
+    /// Every position at which `rule_id` can stop when matched from `start`.
+    /// Keeping all of them instead of the first means no alternative is ever
+    /// committed to, so any grammar works as long as it is not left-recursive.
+    fn ends(&mut self, rule_id: u32, start: usize) -> Vec<usize> {
+        match &self.rules[&rule_id] {
+            Rule::Single(ch) => {
+                if self.message[start..].starts_with(*ch) {
+                    let end = start + ch.len_utf8();
+                    if end > self.furthest {
+                        self.furthest = end;
+                        self.failed.clear();
+                    }
+                    vec![end]
+                } else {
+                    if start > self.furthest {
+                        self.furthest = start;
+                        self.failed.clear();
+                    }
+                    if start == self.furthest {
+                        self.failed.insert((rule_id, *ch));
+                    }
+                    vec![]
+                }
+            }
+            Rule::Sequence(ids) => self.sequence_ends(ids, start),
+            Rule::Either(alternatives) => {
+                let mut ends: Vec<_> = alternatives
+                    .iter()
+                    .flat_map(|ids| self.sequence_ends(ids, start))
+                    .collect();
+                ends.sort_unstable();
+                ends.dedup();
+                ends
             }
+        }
+    }
 
-            remainder.is_empty() && count_31 > 0 && count_42 > count_31
+    fn sequence_ends(&mut self, ids: &[u32], start: usize) -> Vec<usize> {
+        let mut ends = vec![start];
+        for &id in ids {
+            let mut next: Vec<_> = ends.iter().flat_map(|&pos| self.ends(id, pos)).collect();
+            next.sort_unstable();
+            next.dedup();
+            ends = next;
+            if ends.is_empty() {
+                break;
+            }
+        }
+        ends
+    }
+
+    /// A derivation of `rule_id` covering exactly `start..end`.
+    fn tree(&mut self, rule_id: u32, start: usize, end: usize) -> Option<Tree> {
+        let children = match &self.rules[&rule_id] {
+            Rule::Single(ch) => {
+                return if self.message[start..end] == *ch.to_string() {
+                    Some(Tree::Leaf {
+                        rule: rule_id,
+                        ch: *ch,
+                    })
+                } else {
+                    None
+                };
+            }
+            Rule::Sequence(ids) => self.sequence_trees(ids, start, end)?,
+            Rule::Either(alternatives) => alternatives
+                .iter()
+                .find_map(|ids| self.sequence_trees(ids, start, end))?,
+        };
+        Some(Tree::Node {
+            rule: rule_id,
+            text: self.message[start..end].to_string(),
+            children,
         })
-        .count()
+    }
+
+    fn sequence_trees(&mut self, ids: &[u32], start: usize, end: usize) -> Option<Vec<Tree>> {
+        let (first, rest) = match ids.split_first() {
+            Some(split) => split,
+            None => return if start == end { Some(vec![]) } else { None },
+        };
+        for mid in self.ends(*first, start) {
+            if mid > end {
+                break;
+            }
+            if let Some(mut trees) = self.sequence_trees(rest, mid, end) {
+                let tree = self.tree(*first, start, mid)?;
+                trees.insert(0, tree);
+                return Some(trees);
+            }
+        }
+        None
+    }
 }
 
-// Took this from others
-// Can't figure out what I did wrong
-// even tho the basic idea is the same
-fn check_valid<'a>(
+/// How `rule_id` derives `message`, or where matching it broke down. Only
+/// fails outright if the grammar cannot be matched at all.
+fn derive(
     rules: &HashMap<u32, Rule>,
-    rule: &Rule,
-    message: &'a str,
-) -> Result<&'a str, Error> {
-    match rule {
-        Rule::Single(ch) => {
-            if message.starts_with(*ch) {
-                Ok(&message[1..])
-            } else {
-                Err(format!("{} does not start with {}", message, ch).into())
+    rule_id: u32,
+    message: &str,
+) -> Result<Result<Tree, Mismatch>, Error> {
+    let mut matcher = Matcher::new(rules, rule_id, message)?;
+    let ends = matcher.ends(rule_id, 0);
+    if ends.contains(&message.len()) {
+        return Ok(Ok(matcher.tree(rule_id, 0, message.len()).unwrap()));
+    }
+    let complete = ends.last().copied();
+    Ok(Err(Mismatch {
+        message: message.to_string(),
+        matched: matcher.furthest.max(complete.unwrap_or(0)),
+        failed: matcher.failed.into_iter().collect(),
+        complete,
+    }))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tree {
+    Leaf {
+        rule: u32,
+        ch: char,
+    },
+    Node {
+        rule: u32,
+        text: String,
+        children: Vec<Tree>,
+    },
+}
+
+impl Tree {
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        match self {
+            Self::Leaf { rule, ch } => {
+                writeln!(f, "{:indent$}{} {:?}", "", rule, ch, indent = depth * 2)
+            }
+            Self::Node {
+                rule,
+                text,
+                children,
+            } => {
+                writeln!(f, "{:indent$}{} {:?}", "", rule, text, indent = depth * 2)?;
+                children
+                    .iter()
+                    .try_for_each(|child| child.write(f, depth + 1))
+            }
+        }
+    }
+}
+
+/// One line per node, children indented under their rule.
+impl fmt::Display for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mismatch {
+    message: String,
+    /// Length of the longest prefix some derivation got through.
+    matched: usize,
+    /// Character rules that failed right after that prefix.
+    failed: Vec<(u32, char)>,
+    /// Longest prefix the whole rule matched, if any.
+    complete: Option<usize>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let found = match self.message[self.matched..].chars().next() {
+            Some(ch) => format!("{:?}", ch),
+            None => "end of message".to_string(),
+        };
+        write!(
+            f,
+            "no match, longest prefix {:?} ({} of {}), then {}",
+            &self.message[..self.matched],
+            self.matched,
+            self.message.len(),
+            found
+        )?;
+        let expected: Vec<_> = self
+            .failed
+            .iter()
+            .map(|(rule, ch)| format!("rule {} expected {:?}", rule, ch))
+            .collect();
+        if !expected.is_empty() {
+            write!(f, "; {}", expected.join(", "))?;
+        }
+        if self.complete == Some(self.matched) {
+            write!(f, "; the start rule already ended there")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Problem {
+    Undefined {
+        rule: u32,
+        missing: u32,
+    },
+    Unreachable(u32),
+    /// A cycle of rules that can each start with the next one.
+    LeftRecursive(Vec<u32>),
+}
+
+impl Problem {
+    /// Undefined rules and left recursion would stop the matcher; an
+    /// unreachable rule is only suspicious.
+    fn is_fatal(&self) -> bool {
+        !matches!(self, Self::Unreachable(_))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Undefined { rule, missing } => {
+                write!(
+                    f,
+                    "error: rule {} refers to undefined rule {}",
+                    rule, missing
+                )
+            }
+            Self::Unreachable(rule) => write!(f, "warning: rule {} is unreachable", rule),
+            Self::LeftRecursive(cycle) => {
+                let path: Vec<_> = cycle
+                    .iter()
+                    .chain(cycle.first())
+                    .map(|id| id.to_string())
+                    .collect();
+                write!(f, "error: left recursion {}", path.join(" -> "))
+            }
+        }
+    }
+}
+
+/// Looks for undefined references, rules not reachable from `start` and
+/// left recursion, in that order of ids.
+fn check_grammar(rules: &HashMap<u32, Rule>, start: u32) -> Vec<Problem> {
+    let mut ids: Vec<_> = rules.keys().copied().collect();
+    ids.sort_unstable();
+    let mut problems = vec![];
+    if !rules.contains_key(&start) {
+        problems.push(Problem::Undefined {
+            rule: start,
+            missing: start,
+        });
+    }
+    for &id in &ids {
+        let mut missing: Vec<_> = rules[&id]
+            .alternatives()
+            .concat()
+            .into_iter()
+            .filter(|child| !rules.contains_key(child))
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        problems.extend(
+            missing
+                .into_iter()
+                .map(|missing| Problem::Undefined { rule: id, missing }),
+        );
+    }
+    if !problems.is_empty() {
+        return problems;
+    }
+
+    let mut reachable = BTreeSet::new();
+    let mut stack = vec![start];
+    while let Some(id) = stack.pop() {
+        if reachable.insert(id) {
+            stack.extend(rules[&id].alternatives().concat());
+        }
+    }
+    problems.extend(
+        ids.iter()
+            .filter(|id| !reachable.contains(id))
+            .map(|&id| Problem::Unreachable(id)),
+    );
+
+    // rules that can match nothing at all, so the rule after them may start
+    let mut nullable = BTreeSet::new();
+    loop {
+        let before = nullable.len();
+        for &id in &ids {
+            if rules[&id]
+                .alternatives()
+                .iter()
+                .any(|ids| ids.iter().all(|c| nullable.contains(c)))
+            {
+                nullable.insert(id);
             }
         }
-        Rule::Sequence(ids) => {
-            let mut remainder = message;
-            for id in ids {
-                remainder = check_valid(rules, &rules[id], remainder)?;
+        if nullable.len() == before {
+            break;
+        }
+    }
+    let left_corners = |id: u32| -> Vec<u32> {
+        let mut corners = vec![];
+        for ids in rules[&id].alternatives() {
+            for &child in ids {
+                corners.push(child);
+                if !nullable.contains(&child) {
+                    break;
+                }
             }
-            Ok(remainder)
         }
-        Rule::Either { left, right } => {
-            let left_res = check_valid(rules, &Rule::Sequence(left.clone()), message);
-            let right_res = check_valid(rules, &Rule::Sequence(right.clone()), message);
-            if left_res.is_ok() {
-                left_res
-            } else if right_res.is_ok() {
-                right_res
-            } else {
-                Err("Nope".into())
+        corners
+    };
+    let mut seen_cycles = BTreeSet::new();
+    for &id in &ids {
+        // breadth first, so the shortest cycle back to `id` is reported
+        let mut parents = HashMap::new();
+        let mut queue = std::collections::VecDeque::from(vec![id]);
+        while let Some(current) = queue.pop_front() {
+            let corners = left_corners(current);
+            if corners.contains(&id) {
+                let mut cycle = vec![current];
+                while let Some(&parent) = parents.get(cycle.last().unwrap()) {
+                    cycle.push(parent);
+                }
+                cycle.reverse();
+                let members: BTreeSet<_> = cycle.iter().copied().collect();
+                if seen_cycles.insert(members) {
+                    problems.push(Problem::LeftRecursive(cycle));
+                }
+                break;
+            }
+            for corner in corners {
+                if corner != id && !parents.contains_key(&corner) {
+                    parents.insert(corner, current);
+                    queue.push_back(corner);
+                }
             }
         }
     }
+    problems
 }
 
 #[derive(Debug, Clone)]
 enum Rule {
     Sequence(Vec<u32>),
-    Either { left: Vec<u32>, right: Vec<u32> },
+    Either(Vec<Vec<u32>>),
     Single(char),
 }
 
-fn count_valid(rules: &[String], messages: &[&str]) -> usize {
-    messages
-        .into_iter()
-        .filter(|m| rules.iter().any(|r| r == *m))
-        .count()
+impl Rule {
+    fn alternatives(&self) -> Vec<&[u32]> {
+        match self {
+            Self::Sequence(ids) => vec![ids],
+            Self::Either(alternatives) => alternatives.iter().map(Vec::as_slice).collect(),
+            Self::Single(_) => vec![],
+        }
+    }
+}
+
+/// Fails on the first rule that refers back to itself, since only a
+/// non-recursive grammar describes a regular language.
+fn check_non_recursive(rules: &HashMap<u32, Rule>, rule_id: u32) -> Result<(), Error> {
+    fn visit(
+        rules: &HashMap<u32, Rule>,
+        id: u32,
+        path: &mut Vec<u32>,
+        done: &mut BTreeSet<u32>,
+    ) -> Result<(), Error> {
+        if done.contains(&id) {
+            return Ok(());
+        }
+        if path.contains(&id) {
+            return Err(format!("Rule {} is recursive", id).into());
+        }
+        path.push(id);
+        let children: Vec<u32> = match &rules[&id] {
+            Rule::Single(_) => vec![],
+            Rule::Sequence(ids) => ids.clone(),
+            Rule::Either(alternatives) => alternatives.concat(),
+        };
+        for child in children {
+            visit(rules, child, path, done)?;
+        }
+        path.pop();
+        done.insert(id);
+        Ok(())
+    }
+
+    visit(rules, rule_id, &mut vec![], &mut BTreeSet::new())
 }
 
-fn build_rule(rules: &HashMap<u32, Rule>, rule_id: u32) -> Vec<String> {
-    fn concat_strs(left: Vec<String>, right: Vec<String>) -> Vec<String> {
-        left.into_iter()
-            .cartesian_product(right.into_iter())
-            .map(|(l, r)| {
-                let mut res = l.clone();
-                res.push_str(&r);
-                res
-            })
-            .collect()
+/// Regex for the strings `rule_id` matches, without anchors.
+fn to_regex(rules: &HashMap<u32, Rule>, rule_id: u32) -> Result<String, Error> {
+    fn sequence(
+        rules: &HashMap<u32, Rule>,
+        ids: &[u32],
+        cache: &mut HashMap<u32, String>,
+    ) -> String {
+        ids.iter().map(|&id| regex(rules, id, cache)).collect()
     }
-    fn build_strs_from_ids(rules: &HashMap<u32, Rule>, ids: &[u32]) -> Vec<String> {
-        let mut res = build_rule(rules, ids[0]);
-        for id in &ids[1..] {
-            let sub_rules = build_rule(rules, *id);
-            res = concat_strs(res, sub_rules);
+
+    fn regex(rules: &HashMap<u32, Rule>, id: u32, cache: &mut HashMap<u32, String>) -> String {
+        if let Some(re) = cache.get(&id) {
+            return re.clone();
         }
-        res
+        let re = match &rules[&id] {
+            Rule::Single(ch) => {
+                let mut re = String::new();
+                if !ch.is_alphanumeric() {
+                    re.push('\\');
+                }
+                re.push(*ch);
+                re
+            }
+            Rule::Sequence(ids) => sequence(rules, ids, cache),
+            Rule::Either(alternatives) => {
+                let alternatives: Vec<_> = alternatives
+                    .iter()
+                    .map(|ids| sequence(rules, ids, cache))
+                    .collect();
+                format!("(?:{})", alternatives.join("|"))
+            }
+        };
+        cache.insert(id, re.clone());
+        re
+    }
+
+    check_non_recursive(rules, rule_id)?;
+    Ok(regex(rules, rule_id, &mut HashMap::new()))
+}
+
+/// Thompson automaton with one copy of each rule per place it is used;
+/// `None` labels an epsilon edge.
+#[derive(Debug, Default)]
+struct Nfa {
+    edges: Vec<Vec<(Option<char>, usize)>>,
+}
+
+impl Nfa {
+    fn add_state(&mut self) -> usize {
+        self.edges.push(vec![]);
+        self.edges.len() - 1
     }
 
-    let res = match &rules[&rule_id] {
-        Rule::Single(c) => vec![c.to_string()],
-        Rule::Sequence(ids) => build_strs_from_ids(rules, ids),
-        Rule::Either { left, right } => {
-            let mut left_res = build_strs_from_ids(rules, left);
-            let right_res = build_strs_from_ids(rules, right);
-            left_res.extend(right_res.into_iter());
-            left_res
+    /// Adds states for `rule_id` and returns its entry and exit.
+    fn add_rule(&mut self, rules: &HashMap<u32, Rule>, rule_id: u32) -> (usize, usize) {
+        match &rules[&rule_id] {
+            Rule::Single(ch) => {
+                let (start, end) = (self.add_state(), self.add_state());
+                self.edges[start].push((Some(*ch), end));
+                (start, end)
+            }
+            Rule::Sequence(ids) => self.add_sequence(rules, ids),
+            Rule::Either(alternatives) => {
+                let (start, end) = (self.add_state(), self.add_state());
+                for ids in alternatives {
+                    let (alt_start, alt_end) = self.add_sequence(rules, ids);
+                    self.edges[start].push((None, alt_start));
+                    self.edges[alt_end].push((None, end));
+                }
+                (start, end)
+            }
         }
-    };
+    }
+
+    fn add_sequence(&mut self, rules: &HashMap<u32, Rule>, ids: &[u32]) -> (usize, usize) {
+        let start = self.add_state();
+        let mut end = start;
+        for &id in ids {
+            let (rule_start, rule_end) = self.add_rule(rules, id);
+            self.edges[end].push((None, rule_start));
+            end = rule_end;
+        }
+        (start, end)
+    }
+
+    fn closure(&self, states: impl IntoIterator<Item = usize>) -> BTreeSet<usize> {
+        let mut seen = BTreeSet::new();
+        let mut stack: Vec<_> = states.into_iter().collect();
+        while let Some(state) = stack.pop() {
+            if seen.insert(state) {
+                stack.extend(
+                    self.edges[state]
+                        .iter()
+                        .filter(|(label, _)| label.is_none())
+                        .map(|&(_, next)| next),
+                );
+            }
+        }
+        seen
+    }
+}
+
+/// Deterministic automaton for a non-recursive rule, so that checking a
+/// message is one table lookup per character.
+#[derive(Debug, Clone)]
+struct Dfa {
+    alphabet: Vec<char>,
+    /// `transitions[state][i]` follows `alphabet[i]`; state 0 is the start.
+    transitions: Vec<Vec<Option<usize>>>,
+    accepting: Vec<bool>,
+}
+
+impl Dfa {
+    /// Subset construction over the Thompson automaton of `rule_id`.
+    fn compile(rules: &HashMap<u32, Rule>, rule_id: u32) -> Result<Self, Error> {
+        check_non_recursive(rules, rule_id)?;
+        let mut nfa = Nfa::default();
+        let (start, end) = nfa.add_rule(rules, rule_id);
+        let alphabet: Vec<char> = nfa
+            .edges
+            .iter()
+            .flatten()
+            .filter_map(|&(label, _)| label)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        let mut dfa = Self {
+            alphabet,
+            transitions: vec![],
+            accepting: vec![],
+        };
+        let mut ids = HashMap::new();
+        let mut pending = vec![nfa.closure(Some(start))];
+        ids.insert(pending[0].clone(), 0);
+        while let Some(set) = pending.pop() {
+            let id = ids[&set];
+            let mut row = vec![None; dfa.alphabet.len()];
+            for (i, &ch) in dfa.alphabet.iter().enumerate() {
+                let next = nfa.closure(
+                    set.iter()
+                        .flat_map(|&state| &nfa.edges[state])
+                        .filter(|&&(label, _)| label == Some(ch))
+                        .map(|&(_, next)| next),
+                );
+                if next.is_empty() {
+                    continue;
+                }
+                let count = ids.len();
+                let next_id = *ids.entry(next.clone()).or_insert_with(|| {
+                    pending.push(next);
+                    count
+                });
+                row[i] = Some(next_id);
+            }
+            if dfa.transitions.len() <= id {
+                dfa.transitions.resize(id + 1, vec![]);
+                dfa.accepting.resize(id + 1, false);
+            }
+            dfa.transitions[id] = row;
+            dfa.accepting[id] = set.contains(&end);
+        }
+        Ok(dfa)
+    }
 
-    res.into_iter().unique().collect()
+    fn accepts(&self, message: &str) -> bool {
+        let mut state = 0;
+        for ch in message.chars() {
+            let next = self
+                .alphabet
+                .iter()
+                .position(|&c| c == ch)
+                .and_then(|i| self.transitions[state][i]);
+            match next {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+        self.accepting[state]
+    }
+
+    fn count_accepted(&self, messages: &[&str]) -> usize {
+        messages.iter().filter(|m| self.accepts(m)).count()
+    }
 }
 
 fn parse_rules(lines: &[&str]) -> Result<HashMap<u32, Rule>, Error> {
-    lines.into_iter().map(|line| parse_rule(line)).collect()
+    let mut rules = HashMap::new();
+    for line in lines {
+        let (id, rule) = parse_rule(line)?;
+        if rules.insert(id, rule).is_some() {
+            return Err(format!("Rule {} is defined twice", id).into());
+        }
+    }
+    Ok(rules)
 }
 
 fn parse_rule(line: &str) -> Result<(u32, Rule), Error> {
     let rule_id: u32 = line
         .split(':')
         .next()
-        .ok_or_else(|| "Invalid line of rule")?
+        .ok_or("Invalid line of rule")?
         .parse()?;
-    let rule_str = line
-        .split(':')
-        .skip(1)
-        .next()
-        .ok_or_else(|| "Invalid line of rule")?
-        .trim();
+    let rule_str = line.split(':').nth(1).ok_or("Invalid line of rule")?.trim();
     let rule_set = if rule_str.contains('|') {
         let nums: Vec<_> = rule_str
             .split('|')
@@ -157,17 +726,14 @@ fn parse_rule(line: &str) -> Result<(u32, Rule), Error> {
                     .collect::<Result<Vec<_>, _>>()
             })
             .collect::<Result<_, _>>()?;
-        Rule::Either {
-            left: nums[0].clone(),
-            right: nums[1].clone(),
-        }
+        Rule::Either(nums)
     } else if rule_str.contains('\"') {
         let ch = rule_str
             .trim()
             .strip_prefix('\"')
             .and_then(|s| s.strip_suffix('\"'))
             .and_then(|s| s.chars().next())
-            .ok_or_else(|| "Invalid line of rule")?;
+            .ok_or("Invalid line of rule")?;
 
         Rule::Single(ch)
     } else {
@@ -201,6 +767,10 @@ fn read(input: &str) -> (Vec<&str>, Vec<&str>) {
 mod tests {
     use super::*;
 
+    fn matches(rules: &HashMap<u32, Rule>, rule_id: u32, message: &str) -> Result<bool, Error> {
+        Ok(Matcher::new(rules, rule_id, message)?.matches_all(rule_id))
+    }
+
     #[test]
     fn test_p1() {
         const SAMPLE: &str = r#"0: 4 1 5
@@ -218,8 +788,13 @@ mod tests {
 
         let (r, m) = read(SAMPLE);
         let rule_map = parse_rules(&r).unwrap();
-        let rules = build_rule(&rule_map, 0);
-        assert_eq!(2, count_valid(&rules, &m));
+        assert!(check_grammar(&rule_map, 0).is_empty());
+        let dfa = Dfa::compile(&rule_map, 0).unwrap();
+        assert_eq!(2, dfa.count_accepted(&m));
+        assert_eq!(
+            "a(?:(?:aa|bb)(?:ab|ba)|(?:ab|ba)(?:aa|bb))b",
+            to_regex(&rule_map, 0).unwrap()
+        );
     }
 
     #[test]
@@ -274,8 +849,121 @@ mod tests {
 
         let (r, m) = read(SAMPLE);
         let rule_map = parse_rules(&r).unwrap();
-        let rules = build_rule(&rule_map, 0);
-        assert_eq!(3, count_valid(&rules, &m));
-        assert_eq!(12, count_valid_loop(&rule_map, &m));
+        let dfa = Dfa::compile(&rule_map, 0).unwrap();
+        assert_eq!(3, dfa.count_accepted(&m));
+        assert_eq!(3, count_matching(&rule_map, &m).unwrap());
+        let loop_map = with_loops(&rule_map).unwrap();
+        assert_eq!(12, count_matching(&loop_map, &m).unwrap());
+        assert!(Dfa::compile(&loop_map, 0).is_err());
+        assert!(to_regex(&loop_map, 0).is_err());
+        // the loops are only reachable from 0, so 42 on its own still compiles
+        assert!(Dfa::compile(&loop_map, 42).is_ok());
+    }
+
+    #[test]
+    fn test_ambiguous() {
+        // taking the first alternative of 1 that fits leaves "aab" for 2
+        let (r, _) = read("0: 1 2\n1: 3 | 3 3\n2: 3 4\n3: \"a\"\n4: \"b\"");
+        let rules = parse_rules(&r).unwrap();
+        assert!(matches(&rules, 0, "aaab").unwrap());
+        assert!(matches(&rules, 0, "aab").unwrap());
+        assert!(!matches(&rules, 0, "ab").unwrap());
+        assert!(!matches(&rules, 0, "aaaab").unwrap());
+
+        // right recursion in both alternatives, as long as the input lasts
+        let (r, _) = read("0: 1 | 1 0\n1: 2 | 2 1 3\n2: \"a\"\n3: \"b\"");
+        let rules = parse_rules(&r).unwrap();
+        assert!(matches(&rules, 0, "aaabaab").unwrap());
+        assert!(!matches(&rules, 0, "aabbb").unwrap());
+        assert!(!matches(&rules, 0, "").unwrap());
+    }
+
+    #[test]
+    fn test_alternatives() {
+        let (r, m) = read("0: 1 1 | 2 | 1 2 1\n1: \"a\"\n2: \"b\"\n\naa\nb\naba\nab\n\n");
+        let rules = parse_rules(&r).unwrap();
+        assert_eq!("(?:aa|b|aba)", to_regex(&rules, 0).unwrap());
+        let dfa = Dfa::compile(&rules, 0).unwrap();
+        let accepted: Vec<_> = m.iter().map(|m| dfa.accepts(m)).collect();
+        assert_eq!(vec![true, true, true, false, false], accepted);
+        assert_eq!(3, count_matching(&rules, &m).unwrap());
+        assert!(!dfa.accepts("abc"));
+    }
+
+    #[test]
+    fn test_derive() {
+        let (r, _) = read("0: 4 1 5\n1: 2 3 | 3 2\n2: 4 4 | 5 5\n3: 4 5 | 5 4\n4: \"a\"\n5: \"b\"");
+        let rules = parse_rules(&r).unwrap();
+        let tree = derive(&rules, 0, "ababbb").unwrap().unwrap();
+        let expected = "\
+0 \"ababbb\"
+  4 'a'
+  1 \"babb\"
+    3 \"ba\"
+      5 'b'
+      4 'a'
+    2 \"bb\"
+      5 'b'
+      5 'b'
+  5 'b'
+";
+        assert_eq!(expected, tree.to_string());
+
+        let mismatch = derive(&rules, 0, "aaabbb").unwrap().unwrap_err();
+        assert_eq!(4, mismatch.matched);
+        assert_eq!(vec![(4, 'a')], mismatch.failed);
+        assert_eq!(
+            "no match, longest prefix \"aaab\" (4 of 6), then 'b'; rule 4 expected 'a'",
+            mismatch.to_string()
+        );
+        let mismatch = derive(&rules, 0, "aaaabbb").unwrap().unwrap_err();
+        assert_eq!(Some(6), mismatch.complete);
+        assert!(mismatch
+            .to_string()
+            .ends_with("the start rule already ended there"));
+        let mismatch = derive(&rules, 0, "abab").unwrap().unwrap_err();
+        assert!(mismatch.to_string().contains("then end of message"));
+    }
+
+    #[test]
+    fn test_check_grammar() {
+        let (r, _) = read("0: 1 2\n1: 1 3 | 3\n2: 4 0 | 7\n3: \"a\"\n4: 5 3\n5: \"b\" \n6: 3");
+        let rules = parse_rules(&r).unwrap();
+        assert_eq!(
+            vec![Problem::Undefined {
+                rule: 2,
+                missing: 7
+            }],
+            check_grammar(&rules, 0)
+        );
+        assert!(check_grammar(&rules, 9)[0].is_fatal());
+
+        let (r, _) = read(
+            "0: 1 2\n1: 1 3 | 3\n2: 4 0 | 3\n3: \"a\"\n4: 5 3\n5: \"b\"\n6: 3\n7: 8 9\n8: \n9: 7",
+        );
+        let rules = parse_rules(&r).unwrap();
+        let problems = check_grammar(&rules, 0);
+        let lines: Vec<_> = problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            vec![
+                "warning: rule 6 is unreachable",
+                "warning: rule 7 is unreachable",
+                "warning: rule 8 is unreachable",
+                "warning: rule 9 is unreachable",
+                "error: left recursion 1 -> 1",
+                "error: left recursion 7 -> 9 -> 7",
+            ],
+            lines
+        );
+        assert!(!problems[0].is_fatal());
+        // the matcher refuses grammars it could never finish on
+        assert_eq!(
+            "error: left recursion 1 -> 1",
+            matches(&rules, 0, "aa").unwrap_err().to_string()
+        );
+        assert!(count_matching(&rules, &["a"]).is_err());
+        assert!(derive(&rules, 9, "a").is_err());
+
+        assert!(parse_rules(&["0: 1", "0: 2"]).is_err());
     }
 }
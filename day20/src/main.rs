@@ -1,26 +1,508 @@
-use std::collections::HashMap;
-
-use itertools::Itertools;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
 
 type Error = Box<dyn std::error::Error>;
 
-const WIDTH: usize = 10;
-const HEIGHT: usize = 10;
+const MONSTER: [&str; 3] = [
+    "                  # ",
+    "#    ##    ##    ###",
+    " #  #  #  #  #  #   ",
+];
+
+fn main() -> Result<(), Error> {
+    let mut pattern = MONSTER.join("\n");
+    let mut overlap = Overlap::Allow;
+    let mut show_tiles = false;
+    let mut separators = false;
+    let mut show_image = false;
+    let mut ppm = None;
+    let mut scale = 4;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--pattern" => pattern = std::fs::read_to_string(value()?)?,
+            "--disjoint" => overlap = Overlap::Disjoint,
+            "--tiles" => show_tiles = true,
+            "--separators" => separators = true,
+            "--image" => show_image = true,
+            "--ppm" => ppm = Some(value()?),
+            "--scale" => scale = value()?.parse()?,
+            s => return Err(format!("Unknown argument {}", s).into()),
+        }
+    }
+    let pattern = parse_pattern(&pattern)?;
 
-fn main() {
     let input = include_str!("..\\input.txt");
-    let tiles = read(&input, "\r\n\r\n").unwrap();
-    let (corners, _tiles) = find_corners(tiles);
-    assert_eq!(28057939502729, corners.into_iter().product::<usize>());
+    let tiles = read(input)?;
+    let (corners, tiles) = find_corners(tiles)?;
+    assert_eq!(28057939502729, corners.iter().product::<usize>());
+    let assembly = assemble(&tiles, &corners)?;
+    if show_tiles {
+        print!("{}", assembly.render(&tiles, separators));
+    }
+    let image = assembly.image(&tiles);
+    let sighting =
+        search(&image, &pattern, overlap).ok_or("Pattern not found in any orientation")?;
+    let highlighted = highlight(&image, &pattern, &sighting);
+    if show_image {
+        print!("{}", to_text(&highlighted));
+    }
+    if let Some(path) = ppm {
+        std::fs::write(path, to_ppm(&highlighted, scale))?;
+    }
+    println!(
+        "{} matches with the image turned {}:",
+        sighting.matches.len(),
+        sighting.orientation
+    );
+    for (row, col) in &sighting.matches {
+        println!("  row {}, column {}", row, col);
+    }
+    println!(
+        "Water roughness: {}",
+        roughness(&image, &pattern, &sighting)
+    );
+    Ok(())
+}
+
+type Grid = Vec<Vec<bool>>;
+
+/// Mirror each row first if `flipped`, then turn clockwise `rotation` times.
+/// Shown as `R90`, or `FR90` when flipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Orientation {
+    rotation: u8,
+    flipped: bool,
+}
+
+impl Orientation {
+    fn all() -> impl Iterator<Item = Self> {
+        (0..8).map(|i| Self {
+            rotation: i % 4,
+            flipped: i >= 4,
+        })
+    }
+
+    fn apply(self, grid: &[Vec<bool>]) -> Grid {
+        let mut grid = grid.to_vec();
+        if self.flipped {
+            grid.iter_mut().for_each(|row| row.reverse());
+        }
+        for _ in 0..self.rotation {
+            grid = rotate(&grid);
+        }
+        grid
+    }
+}
+
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flip = if self.flipped { "F" } else { "" };
+        write!(f, "{}R{}", flip, u32::from(self.rotation) * 90)
+    }
+}
+
+fn rotate(grid: &[Vec<bool>]) -> Grid {
+    let height = grid.len();
+    let width = grid.first().map_or(0, Vec::len);
+    (0..width)
+        .map(|row| (0..height).map(|col| grid[height - 1 - col][row]).collect())
+        .collect()
+}
+
+fn left_edge(grid: &[Vec<bool>]) -> Vec<bool> {
+    grid.iter().map(|row| row[0]).collect()
+}
+
+fn right_edge(grid: &[Vec<bool>]) -> Vec<bool> {
+    grid.iter().map(|row| row[row.len() - 1]).collect()
+}
+
+/// Tiles laid out row by row, each turned to fit its neighbors.
+#[derive(Debug, Clone)]
+struct Assembly {
+    grid: Vec<Vec<(usize, Orientation)>>,
+}
+
+impl Assembly {
+    /// Every tile with its border stripped, joined into one picture.
+    fn image(&self, tiles: &HashMap<usize, Tile>) -> Grid {
+        let mut image = vec![];
+        for row in &self.grid {
+            let inner: Vec<Grid> = row
+                .iter()
+                .map(|(id, orientation)| {
+                    let dots = tiles[id].oriented(*orientation);
+                    let size = dots.len();
+                    dots[1..size - 1]
+                        .iter()
+                        .map(|line| line[1..line.len() - 1].to_vec())
+                        .collect()
+                })
+                .collect();
+            for line in 0..inner[0].len() {
+                image.push(inner.iter().flat_map(|dots| dots[line].clone()).collect());
+            }
+        }
+        image
+    }
+}
+
+impl Assembly {
+    /// The tiles as placed, borders included, each under its id and
+    /// orientation. `separators` puts a space between tiles and a blank line
+    /// between rows of tiles.
+    fn render(&self, tiles: &HashMap<usize, Tile>, separators: bool) -> String {
+        let gap = if separators { " " } else { "" };
+        let mut out = String::new();
+        for (idx, row) in self.grid.iter().enumerate() {
+            if separators && idx > 0 {
+                out.push('\n');
+            }
+            let placed: Vec<_> = row
+                .iter()
+                .map(|(id, orientation)| tiles[id].oriented(*orientation))
+                .collect();
+            let size = placed[0].len();
+            let labels: Vec<_> = row
+                .iter()
+                .map(|(id, orientation)| {
+                    let label = format!("{} {}", id, orientation);
+                    format!("{:<size$.size$}", label, size = size)
+                })
+                .collect();
+            out.push_str(labels.join(gap).trim_end());
+            out.push('\n');
+            for line in 0..size {
+                let pixels: Vec<String> = placed
+                    .iter()
+                    .map(|dots| dots[line].iter().map(|&set| pixel(set)).collect())
+                    .collect();
+                out.push_str(&pixels.join(gap));
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+fn pixel(set: bool) -> char {
+    if set {
+        '#'
+    } else {
+        '.'
+    }
+}
+
+/// Starts from a corner turned so that its unmatched edges face up and left,
+/// then fills each row from the left, matching against the placed neighbors.
+fn assemble(tiles: &HashMap<usize, Tile>, corners: &[usize]) -> Result<Assembly, Error> {
+    let side = (tiles.len() as f64).sqrt().round() as usize;
+    if side * side != tiles.len() {
+        return Err(format!("{} tiles do not form a square", tiles.len()).into());
+    }
+    let corner = &tiles[corners.first().ok_or("No corner tile")?];
+    let shared = |tile: &Tile, edge: &[bool]| {
+        let key = canonical(edge);
+        tile.neighbors
+            .iter()
+            .any(|id| tiles[id].edge_keys().contains(&key))
+    };
+    let start = Orientation::all()
+        .find(|o| {
+            let dots = corner.oriented(*o);
+            !shared(corner, &dots[0]) && !shared(corner, &left_edge(&dots))
+        })
+        .ok_or_else(|| format!("Corner {} does not fit", corner.id))?;
+
+    let mut placed = HashMap::new();
+    let mut grid: Vec<Vec<(usize, Orientation)>> = vec![];
+    for row in 0..side {
+        let mut line = vec![];
+        for col in 0..side {
+            if row == 0 && col == 0 {
+                line.push((corner.id, start));
+                placed.insert(corner.id, corner.oriented(start));
+                continue;
+            }
+            let left = (col > 0).then(|| &line[col - 1]);
+            let above = (row > 0).then(|| &grid[row - 1][col]);
+            let anchor = left.or(above).map(|&(id, _): &(usize, Orientation)| id);
+            let candidates = anchor.map_or(&[][..], |id| &tiles[&id].neighbors[..]);
+            let fit = candidates
+                .iter()
+                .filter(|id| !placed.contains_key(*id))
+                .flat_map(|&id| Orientation::all().map(move |o| (id, o)))
+                .find(|&(id, o)| {
+                    let dots = tiles[&id].oriented(o);
+                    let fits_left = left.is_none_or(|(left_id, _)| {
+                        right_edge(&placed[left_id]) == left_edge(&dots)
+                    });
+                    let fits_above =
+                        above.is_none_or(|(above_id, _)| placed[above_id].last() == dots.first());
+                    fits_left && fits_above
+                })
+                .ok_or_else(|| format!("No tile fits at row {}, column {}", row, col))?;
+            placed.insert(fit.0, tiles[&fit.0].oriented(fit.1));
+            line.push(fit);
+        }
+        grid.push(line);
+    }
+    Ok(Assembly { grid })
+}
+
+/// `#` cells of a pattern drawn with `#` and anything else; lines may be
+/// ragged.
+fn parse_pattern(text: &str) -> Result<Grid, Error> {
+    let mut lines: Vec<_> = text.lines().map(str::trim_end).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let pattern: Grid = lines
+        .iter()
+        .map(|line| {
+            let mut row: Vec<_> = line.chars().map(|ch| ch == '#').collect();
+            row.resize(width, false);
+            row
+        })
+        .collect();
+    if !pattern.iter().flatten().any(|&set| set) {
+        return Err("Pattern has no # cells".into());
+    }
+    Ok(pattern)
+}
+
+/// Whether matches of a pattern may share `#` cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overlap {
+    Allow,
+    /// Drops any match that shares a cell with one found earlier in
+    /// reading order.
+    Disjoint,
+}
+
+/// Where a pattern showed up, as top-left corners in the turned image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Sighting {
+    orientation: Orientation,
+    matches: Vec<(usize, usize)>,
+}
+
+fn pattern_cells(pattern: &[Vec<bool>]) -> Vec<(usize, usize)> {
+    pattern
+        .iter()
+        .enumerate()
+        .flat_map(|(r, line)| {
+            line.iter()
+                .enumerate()
+                .filter(|(_, &set)| set)
+                .map(move |(c, _)| (r, c))
+        })
+        .collect()
+}
+
+/// Top-left corners of every place `pattern` fits in `image`.
+fn find_pattern(
+    image: &[Vec<bool>],
+    pattern: &[Vec<bool>],
+    overlap: Overlap,
+) -> Vec<(usize, usize)> {
+    let cells = pattern_cells(pattern);
+    let height = pattern.len();
+    let width = pattern.first().map_or(0, Vec::len);
+    let mut found = vec![];
+    let mut covered = HashSet::new();
+    for row in 0..(image.len() + 1).saturating_sub(height) {
+        for col in 0..(image[row].len() + 1).saturating_sub(width) {
+            if !cells.iter().all(|&(r, c)| image[row + r][col + c]) {
+                continue;
+            }
+            if overlap == Overlap::Disjoint {
+                if cells
+                    .iter()
+                    .any(|&(r, c)| covered.contains(&(row + r, col + c)))
+                {
+                    continue;
+                }
+                covered.extend(cells.iter().map(|&(r, c)| (row + r, col + c)));
+            }
+            found.push((row, col));
+        }
+    }
+    found
+}
+
+/// The orientation of the image showing the most matches, if any does.
+fn search(image: &[Vec<bool>], pattern: &[Vec<bool>], overlap: Overlap) -> Option<Sighting> {
+    Orientation::all()
+        .map(|orientation| Sighting {
+            orientation,
+            matches: find_pattern(&orientation.apply(image), pattern, overlap),
+        })
+        .filter(|sighting| !sighting.matches.is_empty())
+        .fold(None, |best: Option<Sighting>, sighting| match best {
+            Some(best) if best.matches.len() >= sighting.matches.len() => Some(best),
+            _ => Some(sighting),
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pixel {
+    Water,
+    Wave,
+    Monster,
+}
+
+impl Pixel {
+    fn symbol(self) -> char {
+        match self {
+            Self::Water => '.',
+            Self::Wave => '#',
+            Self::Monster => 'O',
+        }
+    }
+
+    fn to_rgb(self) -> [u8; 3] {
+        match self {
+            Self::Water => [0x10, 0x30, 0x60],
+            Self::Wave => [0x60, 0xa0, 0xe0],
+            Self::Monster => [0xe0, 0x40, 0x20],
+        }
+    }
 }
 
-fn find_corners(mut tiles: Vec<Tile>) -> (Vec<usize>, HashMap<usize, Tile>) {
-    let neighbors: HashMap<_, _> = tiles
+/// The image turned as in `sighting`, with the pattern cells marked.
+fn highlight(image: &[Vec<bool>], pattern: &[Vec<bool>], sighting: &Sighting) -> Vec<Vec<Pixel>> {
+    let mut pixels: Vec<Vec<_>> = sighting
+        .orientation
+        .apply(image)
         .iter()
-        .map(|tile| tile.find_neighbors(&tiles))
+        .map(|line| {
+            line.iter()
+                .map(|&set| if set { Pixel::Wave } else { Pixel::Water })
+                .collect()
+        })
         .collect();
+    let cells = pattern_cells(pattern);
+    for &(row, col) in &sighting.matches {
+        for &(r, c) in &cells {
+            pixels[row + r][col + c] = Pixel::Monster;
+        }
+    }
+    pixels
+}
+
+fn to_text(pixels: &[Vec<Pixel>]) -> String {
+    pixels
+        .iter()
+        .map(|line| {
+            let mut line: String = line.iter().map(|p| p.symbol()).collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+fn to_ppm(pixels: &[Vec<Pixel>], scale: usize) -> Vec<u8> {
+    let height = pixels.len() * scale;
+    let width = pixels.first().map_or(0, Vec::len) * scale;
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.reserve(width * height * 3);
+    for row in pixels {
+        for _ in 0..scale {
+            for pixel in row {
+                for _ in 0..scale {
+                    data.extend_from_slice(&pixel.to_rgb());
+                }
+            }
+        }
+    }
+    data
+}
+
+/// `#` cells of the image not covered by any of the sighted patterns.
+fn roughness(image: &[Vec<bool>], pattern: &[Vec<bool>], sighting: &Sighting) -> usize {
+    let cells = pattern_cells(pattern);
+    let covered: HashSet<_> = sighting
+        .matches
+        .iter()
+        .flat_map(|&(row, col)| cells.iter().map(move |&(r, c)| (row + r, col + c)))
+        .collect();
+    let total = image.iter().flatten().filter(|&&set| set).count();
+    total - covered.len()
+}
+
+/// Edge pixels as bits, first pixel highest.
+fn encode(edge: &[bool]) -> u64 {
+    edge.iter().fold(0, |acc, &set| acc << 1 | u64::from(set))
+}
+
+/// The same for an edge and its mirror image, since either tile may be flipped.
+fn canonical(edge: &[bool]) -> u64 {
+    let reversed: Vec<_> = edge.iter().rev().copied().collect();
+    encode(edge).min(encode(&reversed))
+}
+
+/// Tiles having each canonical edge, in input order.
+fn edge_index(tiles: &[Tile]) -> HashMap<u64, Vec<usize>> {
+    let mut index: HashMap<u64, Vec<usize>> = HashMap::new();
+    for tile in tiles {
+        for key in tile.edge_keys().iter() {
+            let ids = index.entry(*key).or_default();
+            if !ids.contains(&tile.id) {
+                ids.push(tile.id);
+            }
+        }
+    }
+    index
+}
+
+/// Edges shared by more than two tiles, which leave the layout undecided.
+fn ambiguous_edges(index: &HashMap<u64, Vec<usize>>) -> Vec<(u64, Vec<usize>)> {
+    let mut ambiguous: Vec<_> = index
+        .iter()
+        .filter(|(_, ids)| ids.len() > 2)
+        .map(|(&key, ids)| (key, ids.clone()))
+        .collect();
+    ambiguous.sort_unstable();
+    ambiguous
+}
+
+fn find_corners(mut tiles: Vec<Tile>) -> Result<(Vec<usize>, HashMap<usize, Tile>), Error> {
+    let index = edge_index(&tiles);
+    let ambiguous = ambiguous_edges(&index);
+    if !ambiguous.is_empty() {
+        let lines: Vec<_> = ambiguous
+            .iter()
+            .map(|(key, ids)| {
+                let ids: Vec<_> = ids.iter().map(|id| id.to_string()).collect();
+                format!("edge {:#x} is shared by tiles {}", key, ids.join(", "))
+            })
+            .collect();
+        return Err(format!("Ambiguous input: {}", lines.join("; ")).into());
+    }
     for tile in tiles.iter_mut() {
-        tile.neighbors = neighbors[&tile.id].clone()
+        let mut neighbors: Vec<_> = tile
+            .edge_keys()
+            .iter()
+            .flat_map(|key| &index[key])
+            .copied()
+            .filter(|&id| id != tile.id)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        tile.neighbors = neighbors;
     }
     let corners = tiles
         .iter()
@@ -30,94 +512,84 @@ fn find_corners(mut tiles: Vec<Tile>) -> (Vec<usize>, HashMap<usize, Tile>) {
         })
         .collect();
     let tiles = tiles.into_iter().map(|t| (t.id, t)).collect();
-    (corners, tiles)
+    Ok((corners, tiles))
 }
 
 #[derive(Debug, Clone)]
 struct Tile {
     id: usize,
-    dots: [[bool; WIDTH]; HEIGHT],
+    /// Square, the same size for every tile.
+    dots: Grid,
     neighbors: Vec<usize>,
 }
 
 impl Tile {
-    fn get_possible_borders(&self) -> Vec<[bool; WIDTH]> {
-        let mut borders = vec![];
-        let top = self.dots[0];
-        borders.push(top);
-        borders.push(Self::reverse_border(top));
-        let btm = self.dots[HEIGHT - 1];
-        borders.push(btm);
-        borders.push(Self::reverse_border(btm));
-
-        let mut left = [false; HEIGHT];
-        for (idx, values) in self.dots.iter().enumerate() {
-            left[idx] = values[0]
-        }
-        borders.push(left);
-        borders.push(Self::reverse_border(left));
-
-        let mut right = [false; HEIGHT];
-        for (idx, values) in self.dots.iter().enumerate() {
-            right[idx] = values[WIDTH - 1]
-        }
-        borders.push(right);
-        borders.push(Self::reverse_border(right));
-
-        borders
-    }
-
-    fn find_neighbors(&self, tiles: &[Tile]) -> (usize, Vec<usize>) {
-        let borders = self.get_possible_borders();
-        let ns = tiles
-            .into_iter()
-            .filter_map(|t| {
-                if t.id == self.id {
-                    None
-                } else if t
-                    .get_possible_borders()
-                    .iter()
-                    .cartesian_product(borders.iter())
-                    .filter(|(other, this)| **other == **this)
-                    .count()
-                    == 2
-                // count in reverse ordered border
-                {
-                    Some(t.id)
-                } else {
-                    None
-                }
-            })
-            .collect();
-        (self.id, ns)
+    fn oriented(&self, orientation: Orientation) -> Grid {
+        orientation.apply(&self.dots)
     }
 
-    fn reverse_border(b: [bool; WIDTH]) -> [bool; WIDTH] {
-        let mut r = [false; WIDTH];
-        for (idx, value) in r.iter_mut().enumerate() {
-            *value = b[WIDTH - 1 - idx]
+    /// Canonical keys of the top, bottom, left and right edges.
+    fn edge_keys(&self) -> [u64; 4] {
+        let dots = &self.dots;
+        [
+            canonical(&dots[0]),
+            canonical(&dots[dots.len() - 1]),
+            canonical(&left_edge(dots)),
+            canonical(&right_edge(dots)),
+        ]
+    }
+}
+
+/// Same layout as the input, so a tile can be read back.
+impl fmt::Display for Tile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Tile {}:", self.id)?;
+        for row in &self.dots {
+            let line: String = row.iter().map(|&set| pixel(set)).collect();
+            writeln!(f, "{}", line)?;
         }
-        r
+        Ok(())
     }
 }
 
-fn read(input: &str, split_pattern: &str) -> Result<Vec<Tile>, Error> {
-    let tiles: Vec<_> = input.split(split_pattern).collect();
+fn read(input: &str) -> Result<Vec<Tile>, Error> {
+    let lines: Vec<_> = input.lines().map(str::trim).collect();
+    let tiles = lines
+        .split(|line| line.is_empty())
+        .filter(|t| !t.is_empty());
 
-    let mut res = vec![];
-    for tile in tiles {
-        let lines: Vec<_> = tile.split('\n').collect();
+    let mut res: Vec<Tile> = vec![];
+    for lines in tiles {
         let id = lines[0]
             .strip_prefix("Tile ")
             .and_then(|s| s.trim().strip_suffix(':'))
-            .ok_or_else(|| "Invalid input")?
+            .ok_or("Invalid input")?
             .parse()?;
 
-        let mut dots = [[false; 10]; 10];
-        for (row, line) in lines.into_iter().skip(1).enumerate() {
-            for (col, ch) in line.trim().char_indices() {
-                dots[row][col] = ch == '#'; // '#' becomes `true`
-            }
+        let dots = lines[1..]
+            .iter()
+            .map(|line| {
+                line.chars()
+                    .map(|ch| match ch {
+                        '#' => Ok(true),
+                        '.' => Ok(false),
+                        _ => Err(format!("Tile {}: invalid pixel {:?}", id, ch)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Grid, _>>()?;
+        let size = res.first().map_or(dots.len(), |first| first.dots.len());
+        if size < 3 {
+            return Err(format!("Tile {}: too small to strip its border", id).into());
+        }
+        if size > 64 {
+            return Err(format!("Tile {}: edges longer than 64 pixels", id).into());
+        }
+        if dots.len() != size || dots.iter().any(|row| row.len() != size) {
+            return Err(format!("Tile {}: expected {}x{} pixels", id, size, size).into());
+        }
+        if res.iter().any(|tile| tile.id == id) {
+            return Err(format!("Tile {} is listed twice", id).into());
         }
         res.push(Tile {
             id,
@@ -243,10 +715,161 @@ Tile 3079:
 
     #[test]
     fn test_p1() {
-        let tiles = read(SAMPLE, "\n\n").unwrap();
+        let tiles = read(SAMPLE).unwrap();
         assert_eq!(
             20899048083289,
-            (find_corners(tiles).0.into_iter().product::<usize>())
+            (find_corners(tiles)
+                .unwrap()
+                .0
+                .into_iter()
+                .product::<usize>())
+        );
+    }
+
+    #[test]
+    fn test_p2() {
+        let (corners, tiles) = find_corners(read(SAMPLE).unwrap()).unwrap();
+        let assembly = assemble(&tiles, &corners).unwrap();
+        assert_eq!(3, assembly.grid.len());
+        let image = assembly.image(&tiles);
+        assert_eq!(24, image.len());
+        assert!(image.iter().all(|line| line.len() == 24));
+        let monster = parse_pattern(&MONSTER.join("\n")).unwrap();
+        let sighting = search(&image, &monster, Overlap::Allow).unwrap();
+        assert_eq!(vec![(2, 2), (16, 1)], sighting.matches);
+        assert_eq!(273, roughness(&image, &monster, &sighting));
+    }
+
+    #[test]
+    fn test_render() {
+        let tiles = read(SAMPLE).unwrap();
+        assert!(tiles[0]
+            .to_string()
+            .starts_with("Tile 2311:\n..##.#..#.\n##..#.....\n"));
+        let shown: String = tiles.iter().map(|t| format!("{}\n", t)).collect();
+        let again = read(&shown).unwrap();
+        assert!(tiles
+            .iter()
+            .zip(&again)
+            .all(|(a, b)| a.id == b.id && a.dots == b.dots));
+
+        let (corners, tiles) = find_corners(tiles).unwrap();
+        let assembly = assemble(&tiles, &corners).unwrap();
+        let plain = assembly.render(&tiles, false);
+        assert_eq!(3 * 11, plain.lines().count());
+        assert!(plain.lines().skip(1).take(10).all(|line| line.len() == 30));
+        let spaced = assembly.render(&tiles, true);
+        assert_eq!(3 * 11 + 2, spaced.lines().count());
+        let labels = spaced.lines().next().unwrap();
+        let (id, orientation) = assembly.grid[0][0];
+        assert!(labels.starts_with(&format!("{} {}", id, orientation)));
+        assert!(spaced.lines().nth(1).unwrap().len() == 32);
+
+        let image = assembly.image(&tiles);
+        let monster = parse_pattern(&MONSTER.join("\n")).unwrap();
+        let sighting = search(&image, &monster, Overlap::Allow).unwrap();
+        let pixels = highlight(&image, &monster, &sighting);
+        let text = to_text(&pixels);
+        assert_eq!(30, text.matches('O').count());
+        assert_eq!(273, text.matches('#').count());
+        // the middle of the second monster, as the puzzle text shows it
+        assert_eq!(Some(".O##.#OO.###OO##..OOO##."), text.lines().nth(17));
+
+        let ppm = to_ppm(&pixels, 2);
+        assert!(ppm.starts_with(b"P6\n48 48\n255\n"));
+        assert_eq!(b"P6\n48 48\n255\n".len() + 48 * 48 * 3, ppm.len());
+    }
+
+    #[test]
+    fn test_edges() {
+        assert_eq!(0b1101, encode(&[true, true, false, true]));
+        assert_eq!(0b1011, canonical(&[true, true, false, true]));
+        assert_eq!(
+            canonical(&[true, false, false]),
+            canonical(&[false, false, true])
+        );
+
+        let tiles = read(SAMPLE).unwrap();
+        let index = edge_index(&tiles);
+        // 12 inner edges are shared by two tiles, the 12 outer ones belong to one
+        assert_eq!(12, index.values().filter(|ids| ids.len() == 2).count());
+        assert_eq!(12, index.values().filter(|ids| ids.len() == 1).count());
+        assert!(ambiguous_edges(&index).is_empty());
+
+        let (_, tiles) = find_corners(tiles).unwrap();
+        assert_eq!(vec![2311, 2729], tiles[&1951].neighbors);
+        assert_eq!(vec![1489, 2311, 2473, 2729], tiles[&1427].neighbors);
+
+        // a copy of a tile under a new id shares all of its edges three ways
+        let mut tiles = read(SAMPLE).unwrap();
+        let mut copy = tiles[0].clone();
+        copy.id = 1;
+        tiles.push(copy);
+        let err = find_corners(tiles).unwrap_err().to_string();
+        assert!(err.starts_with("Ambiguous input: edge "));
+        assert!(err.contains("is shared by tiles 2311, 1427, 1"));
+    }
+
+    #[test]
+    fn test_tile_size() {
+        let small = "Tile 1:\n#.#\n...\n##.\n\nTile 2:\n#..\n.#.\n..#\n";
+        let tiles = read(small).unwrap();
+        assert_eq!(3, tiles[1].dots.len());
+        assert!(
+            read("Tile 1:\n#.#\n...\n##.\n\nTile 2:\n#...\n.#..\n..#.\n....")
+                .unwrap_err()
+                .to_string()
+                .contains("Tile 2: expected 3x3")
+        );
+        assert!(read("Tile 1:\n#.#\n...\n##").is_err());
+        assert!(read("Tile 1:\n#.\n..").is_err());
+        assert!(read("Tile 1:\n#.#\n.x.\n##.").is_err());
+        assert!(read("Tile 1:\n#.#\n...\n##.\n\nTile 1:\n#.#\n...\n##.").is_err());
+    }
+
+    #[test]
+    fn test_patterns() {
+        let image = parse_pattern("####.\n####.\n.....").unwrap();
+        let square = parse_pattern("##\n##\n\n").unwrap();
+        assert_eq!(
+            vec![(0, 0), (0, 1), (0, 2)],
+            find_pattern(&image, &square, Overlap::Allow)
+        );
+        assert_eq!(
+            vec![(0, 0), (0, 2)],
+            find_pattern(&image, &square, Overlap::Disjoint)
+        );
+        // a ragged pattern is padded, and found in the turned image
+        let corner = parse_pattern("#\n##").unwrap();
+        assert_eq!(vec![true, false], corner[0]);
+        let sighting = search(&image, &corner, Overlap::Disjoint).unwrap();
+        assert_eq!(2, sighting.matches.len());
+        assert_eq!(2, roughness(&image, &corner, &sighting));
+        assert!(parse_pattern(". .\n").is_err());
+        assert_eq!(
+            None,
+            search(&image, &parse_pattern("#.#.#").unwrap(), Overlap::Allow)
         );
     }
+
+    #[test]
+    fn test_orientation() {
+        let grid = parse_pattern("#..\n##.").unwrap();
+        let turned = Orientation {
+            rotation: 1,
+            flipped: false,
+        }
+        .apply(&grid);
+        assert_eq!(parse_pattern("##\n#.\n..").unwrap(), turned);
+        let flipped = Orientation {
+            rotation: 0,
+            flipped: true,
+        }
+        .apply(&grid);
+        assert_eq!(parse_pattern("..#\n.##").unwrap(), flipped);
+        let mut seen: Vec<_> = Orientation::all().map(|o| o.apply(&grid)).collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(8, seen.len());
+    }
 }